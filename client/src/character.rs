@@ -1,26 +1,52 @@
 use crate::components::LocallyControlled;
 use crate::input::PlayerInputController;
+use crate::interpolation::{InterpolationBuffer, InterpolationSettings, RenderTick};
+use crate::net::ServerInfo;
 use shared::avian3d::spatial_query::SpatialQuery;
 use shared::bevy::prelude::*;
 use shared::bevy_quinnet::shared::ClientId;
 use shared::character::*;
 use shared::utils::move_towards;
+use shared::weapons::WeaponInventory;
+use shared::TICKRATE;
 
 const VISUALS_CORRECT_SPEED: f32 = 10.0;
 
+/// The weapon held by a remote character, `weapon_config_tag` is what it currently shows.
+#[derive(Component)]
+pub struct WeaponVisuals {
+    pub weapon_config_tag: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_character(
     meshes: &mut Assets<Mesh>,
     materials: &mut Assets<StandardMaterial>,
     commands: &mut Commands,
     owner_peer_id: ClientId,
     position: &Vec3,
+    yaw: f32,
+    constants: &CharacterConstants,
     local: bool,
-) {
-    let entity = shared::character::spawn_character(commands, owner_peer_id, position);
+) -> Entity {
+    // the weapons come with the character's first snapshot
+    let entity = shared::character::spawn_character(
+        commands,
+        owner_peer_id,
+        position,
+        yaw,
+        constants,
+        WeaponInventory::default(),
+    );
     if local {
         commands.entity(entity).insert(LocallyControlled);
+    } else {
+        commands
+            .entity(entity)
+            .insert(InterpolationBuffer::default());
     }
     spawn_character_visuals(meshes, materials, commands, entity, position, local);
+    entity
 }
 
 fn spawn_character_visuals(
@@ -35,54 +61,158 @@ fn spawn_character_visuals(
         CharacterVisuals { belongs_to },
         PbrBundle {
             mesh: meshes.add(Mesh::from(Cylinder {
-                radius: 0.5,
-                half_height: 0.5,
+                radius: CHARACTER_RADIUS,
+                half_height: CHARACTER_HEIGHT / 2.0,
             })),
             material: materials.add(Color::srgb(0.5, 0.5, 1.0)),
-            transform: Transform::from_translation(position.clone()),
+            transform: Transform::from_translation(*position),
             ..default()
         },
     ));
 
     if local {
         cmd.insert(LocallyControlled);
+    } else {
+        cmd.with_children(|parent| {
+            parent.spawn((
+                WeaponVisuals {
+                    weapon_config_tag: None,
+                },
+                PbrBundle {
+                    mesh: meshes.add(Mesh::from(Cuboid {
+                        half_size: Vec3::new(0.08, 0.08, 0.4),
+                    })),
+                    material: materials.add(Color::WHITE),
+                    transform: Transform::from_translation(Vec3::new(0.4, 0.2, -0.3)),
+                    ..default()
+                },
+            ));
+        });
+    }
+}
+
+/// The visuals mesh is built at the default size and scaled to the character's actual collider.
+fn visuals_scale(char_collider: &CharacterCollider) -> Vec3 {
+    let radius_scale = char_collider.radius / CHARACTER_RADIUS;
+    Vec3::new(
+        radius_scale,
+        char_collider.height / CHARACTER_HEIGHT,
+        radius_scale,
+    )
+}
+
+/// Each weapon gets its own color until we have proper models.
+fn weapon_color(weapon_config_tag: &str) -> Color {
+    let hash = weapon_config_tag.bytes().fold(0u32, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as u32)
+    });
+    Color::hsl((hash % 360) as f32, 0.7, 0.5)
+}
+
+pub fn update_weapon_visuals_system(
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    characters: Query<&WeaponInventory>,
+    visuals: Query<(&CharacterVisuals, &Children)>,
+    mut weapon_visuals: Query<(&mut WeaponVisuals, &Handle<StandardMaterial>)>,
+) {
+    for (char_visuals, children) in visuals.iter() {
+        let Ok(inventory) = characters.get(char_visuals.belongs_to) else {
+            continue;
+        };
+        let active_tag = inventory
+            .active_weapon()
+            .map(|weapon| weapon.weapon_config_tag.clone());
+
+        for child in children.iter() {
+            if let Ok((mut weapon_visuals, material_handle)) = weapon_visuals.get_mut(*child) {
+                if weapon_visuals.weapon_config_tag == active_tag {
+                    continue;
+                }
+                if let Some(material) = materials.get_mut(material_handle) {
+                    material.base_color = active_tag
+                        .as_deref()
+                        .map(weapon_color)
+                        .unwrap_or(Color::WHITE);
+                }
+                weapon_visuals.weapon_config_tag = active_tag.clone();
+            }
+        }
     }
 }
 
 pub fn move_system(
-    fixed_time: Res<Time<Fixed>>,
+    server_info: Option<Res<ServerInfo>>,
     mut input_controller: ResMut<PlayerInputController>,
-    mut spatial_query: SpatialQuery,
+    spatial_query: SpatialQuery,
     mut characters: Query<
-        (&mut CharacterState, &mut Transform, &CharacterConstants),
+        (
+            &mut CharacterState,
+            &mut CharacterCollider,
+            &mut Transform,
+            &CharacterConstants,
+        ),
         With<LocallyControlled>,
     >,
+    remote_characters: Query<
+        (&CharacterState, &CharacterCollider, &Transform),
+        Without<LocallyControlled>,
+    >,
 ) {
-    for (mut char_state, mut char_xform, char_constants /*mut controller*/) in characters.iter_mut()
+    let Some(server_info) = server_info else {
+        return;
+    };
+    // others are wherever the server last told us, the server does the same with their
+    // positions from before the tick's moves
+    let obstacles = remote_characters
+        .iter()
+        .map(|(char_state, char_collider, char_xform)| {
+            CharacterObstacle::new(
+                char_state.owner_client_id,
+                char_xform.translation,
+                char_collider,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (mut char_state, mut char_collider, mut char_xform, char_constants) in characters.iter_mut()
     {
         move_character(
-            input_controller.latest_input.compute_wish_dir(),
-            &mut spatial_query,
+            &input_controller.latest_input,
+            &spatial_query,
+            &obstacles,
             &mut char_state,
+            &mut char_collider,
             &mut char_xform,
             char_constants,
-            fixed_time.delta_seconds(),
+            server_info.tick_seconds(),
         );
+        // knockback is recorded after the move of the input it happened on, so this only finds
+        // something when we are replaying inputs during reconciliation
+        let knockback = input_controller.knockback_after(input_controller.latest_input.id);
+        if knockback != Vec3::ZERO {
+            char_state.apply_impulse(knockback);
+        }
         input_controller.latest_input.final_position = char_xform.translation;
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn update_locally_controlled_visuals_system(
     time: Res<Time>,
     fixed_time: Res<Time<Fixed>>,
-    mut char_state: Query<(&mut CharacterState, &Transform), With<LocallyControlled>>,
+    mut char_state: Query<
+        (&mut CharacterState, &Transform, &CharacterCollider),
+        With<LocallyControlled>,
+    >,
     mut visuals: Query<
         (&mut Transform, &CharacterVisuals),
         (With<LocallyControlled>, Without<CharacterState>),
     >,
 ) {
     if let Ok((mut visuals_transform, char_visuals)) = visuals.get_single_mut() {
-        if let Ok((mut char_state, char_transform)) = char_state.get_mut(char_visuals.belongs_to) {
+        if let Ok((mut char_state, char_transform, char_collider)) =
+            char_state.get_mut(char_visuals.belongs_to)
+        {
             let time_diff = time.elapsed_seconds() - fixed_time.elapsed_seconds();
             let fraction = time_diff / fixed_time.delta_seconds();
 
@@ -106,6 +236,7 @@ pub fn update_locally_controlled_visuals_system(
                 corrected_position,
                 char_state.velocity.length() * time.delta_seconds(),
             );
+            visuals_transform.scale = visuals_scale(char_collider);
 
             // we then lerp the offset to zero so we never stray too far from the actual position
             let adjusted_offset = char_state
@@ -116,39 +247,40 @@ pub fn update_locally_controlled_visuals_system(
     }
 }
 
+/// Remote characters are drawn from their interpolation buffer at the render tick.
+#[allow(clippy::type_complexity)]
 pub fn update_visuals_system(
-    time: Res<Time>,
-    fixed_time: Res<Time<Fixed>>,
-    char_state: Query<(&CharacterState, &Transform), Without<LocallyControlled>>,
+    settings: Res<InterpolationSettings>,
+    render_tick: Res<RenderTick>,
+    server_info: Option<Res<ServerInfo>>,
+    char_state: Query<(&InterpolationBuffer, &Transform, &CharacterCollider)>,
     mut visuals: Query<
         (&mut Transform, &CharacterVisuals),
-        (Without<CharacterState>, Without<LocallyControlled>),
+        (Without<InterpolationBuffer>, Without<LocallyControlled>),
     >,
 ) {
+    let tickrate = server_info
+        .map(|server_info| server_info.tickrate)
+        .unwrap_or(TICKRATE);
     for (mut visuals_transform, char_visuals) in visuals.iter_mut() {
-        if let Ok((char_state, char_transform)) = char_state.get(char_visuals.belongs_to) {
-            let time_diff = time.elapsed_seconds() - fixed_time.elapsed_seconds();
-            let fraction = time_diff / fixed_time.delta_seconds();
-            let extrapolated_position =
-                char_transform.translation + (char_state.velocity * fixed_time.delta_seconds());
-            // this is where we are within the final extrapolated position and the actual position
-            let target_position = char_transform
-                .translation
-                .lerp(extrapolated_position, fraction);
-            visuals_transform.translation = target_position;
+        if let Ok((buffer, char_transform, char_collider)) = char_state.get(char_visuals.belongs_to)
+        {
+            // until the clock is synced the latest snapshot is all we can show
+            visuals_transform.translation = render_tick
+                .0
+                .and_then(|tick| buffer.sample(tick, tickrate, settings.max_extrapolation_seconds))
+                .unwrap_or(char_transform.translation);
+            visuals_transform.scale = visuals_scale(char_collider);
         }
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn update_camera_system(
     mut camera_query: Query<&mut Transform, With<Camera3d>>,
     character: Query<
-        &Transform,
-        (
-            With<CharacterState>,
-            With<LocallyControlled>,
-            Without<Camera3d>,
-        ),
+        (&CharacterState, &Transform, &CharacterConstants),
+        (With<LocallyControlled>, Without<Camera3d>),
     >,
     visuals: Query<
         &Transform,
@@ -162,8 +294,9 @@ pub fn update_camera_system(
 ) {
     if let Ok(visuals_global_transform) = visuals.get_single() {
         if let Ok(mut camera_transform) = camera_query.get_single_mut() {
-            if let Ok(character_transform) = character.get_single() {
-                camera_transform.translation = visuals_global_transform.translation;
+            if let Ok((char_state, character_transform, char_constants)) = character.get_single() {
+                camera_transform.translation = visuals_global_transform.translation
+                    + Vec3::Y * char_state.eye_height(char_constants);
                 camera_transform.rotation = character_transform.rotation;
             }
         }
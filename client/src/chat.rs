@@ -0,0 +1,142 @@
+use shared::bevy::input::keyboard::{Key, KeyboardInput};
+use shared::bevy::input::ButtonState;
+use shared::bevy::prelude::*;
+use shared::bevy_quinnet::client::QuinnetClient;
+use shared::chat::{sanitize_chat_message, ChatMessage, ChatSender, MAX_CHAT_MESSAGE_LENGTH};
+use shared::protocol::{ClientChannels, ClientMessage};
+
+// how long messages stay up when not typing, and how many are shown at once
+const CHAT_MESSAGE_SECONDS: f32 = 10.0;
+const CHAT_HISTORY_LENGTH: usize = 8;
+
+#[derive(Resource, Default)]
+pub struct ChatState {
+    /// While typing, key presses go to the chat box instead of moving the character.
+    pub is_typing: bool,
+    pub team_only: bool,
+    pub draft: String,
+    /// Received messages with the `Time::elapsed_seconds` they arrived at, newest last.
+    pub history: Vec<(ChatMessage, f32)>,
+}
+
+impl ChatState {
+    pub fn receive(&mut self, message: ChatMessage, elapsed_seconds: f32) {
+        self.history.push((message, elapsed_seconds));
+        let overflow = self.history.len().saturating_sub(CHAT_HISTORY_LENGTH);
+        self.history.drain(..overflow);
+    }
+
+    fn close(&mut self) {
+        self.is_typing = false;
+        self.draft.clear();
+    }
+}
+
+#[derive(Component)]
+pub struct ChatText;
+
+pub fn setup_chat_system(mut commands: Commands) {
+    commands.spawn((
+        ChatText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 22.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(56.0),
+            left: Val::Px(12.0),
+            ..default()
+        }),
+    ));
+}
+
+/// T or enter opens the chat box, Y opens it for team chat. Enter sends, escape cancels.
+pub fn chat_input_system(
+    client: Res<QuinnetClient>,
+    mut chat: ResMut<ChatState>,
+    mut keyboard_events: EventReader<KeyboardInput>,
+) {
+    if !chat.is_typing {
+        for event in keyboard_events.read() {
+            if event.state != ButtonState::Pressed {
+                continue;
+            }
+            match event.key_code {
+                KeyCode::KeyT | KeyCode::Enter => chat.team_only = false,
+                KeyCode::KeyY => chat.team_only = true,
+                _ => continue,
+            }
+            // the rest of this frame's keys belong to the game, not the message
+            chat.is_typing = true;
+            keyboard_events.clear();
+            return;
+        }
+        return;
+    }
+
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Enter => {
+                if let Some(text) = sanitize_chat_message(&chat.draft) {
+                    if let Err(err) = client.connection().send_message_on(
+                        ClientChannels::Events,
+                        ClientMessage::ChatMessage {
+                            text,
+                            team_only: chat.team_only,
+                        },
+                    ) {
+                        shared::bevy::log::error!("Failed to send chat message: {:?}", err);
+                    }
+                }
+                chat.close();
+            }
+            Key::Escape => chat.close(),
+            Key::Backspace => {
+                chat.draft.pop();
+            }
+            Key::Space => chat.draft.push(' '),
+            Key::Character(characters) if chat.draft.chars().count() < MAX_CHAT_MESSAGE_LENGTH => {
+                chat.draft.push_str(characters);
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn update_chat_text_system(
+    time: Res<Time>,
+    chat: Res<ChatState>,
+    mut chat_text: Query<&mut Text, With<ChatText>>,
+) {
+    let Ok(mut text) = chat_text.get_single_mut() else {
+        return;
+    };
+
+    let now = time.elapsed_seconds();
+    let mut lines: Vec<String> = chat
+        .history
+        .iter()
+        .filter(|(_, received_at)| chat.is_typing || now - received_at < CHAT_MESSAGE_SECONDS)
+        .map(|(message, _)| {
+            let team_prefix = if message.team_only { "(team) " } else { "" };
+            match &message.sender {
+                ChatSender::Player { username, .. } => {
+                    format!("{}{}: {}", team_prefix, username, message.text)
+                }
+                ChatSender::Server => format!("* {}", message.text),
+            }
+        })
+        .collect();
+    if chat.is_typing {
+        let prompt = if chat.team_only { "say_team" } else { "say" };
+        lines.push(format!("{}: {}_", prompt, chat.draft));
+    }
+    text.sections[0].value = lines.join("\n");
+}
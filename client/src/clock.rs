@@ -0,0 +1,174 @@
+use shared::bevy::prelude::*;
+use shared::bevy_quinnet::client::QuinnetClient;
+use shared::protocol::{ClientChannels, ClientMessage};
+
+use crate::input::PlayerInputController;
+use crate::net::ServerInfo;
+
+const PING_INTERVAL_SECONDS: f64 = 0.5;
+// how much of each new sample goes into the estimates, lower is smoother but slower to follow
+const RTT_SMOOTHING: f32 = 0.125;
+const JITTER_SMOOTHING: f32 = 0.25;
+const OFFSET_SMOOTHING: f64 = 0.1;
+// how much faster or slower we tick for every tick we're off our target lead, and the most we
+// ever do, small enough that nobody notices the game running faster or slower
+const DILATION_PER_TICK: f64 = 0.01;
+const MAX_DILATION: f64 = 0.05;
+
+/// Our estimate of the connection and of the server's clock, from pinging the server.
+#[derive(Resource, Default)]
+pub struct ClockSync {
+    /// Smoothed round trip time, in seconds.
+    pub rtt: f32,
+    /// How much round trips vary from `rtt`, in seconds.
+    pub jitter: f32,
+    /// The server's clock minus ours, in seconds. The server's clock is its tick over its tickrate.
+    pub offset: f64,
+    /// How much faster (positive) or slower our fixed step currently runs than the server's.
+    pub dilation: f64,
+    /// Set once the first pong came back, nothing above means anything before that.
+    pub is_synced: bool,
+    last_ping_seconds: Option<f64>,
+}
+
+impl ClockSync {
+    /// Takes in a pong for the ping we sent at `client_seconds`, received at `now_seconds`.
+    pub fn receive_pong(
+        &mut self,
+        client_seconds: f64,
+        server_tick: u32,
+        tickrate: u32,
+        now_seconds: f64,
+    ) {
+        let rtt = (now_seconds - client_seconds).max(0.0) as f32;
+        // the server replied about half a round trip ago
+        let server_seconds = server_tick as f64 / tickrate as f64 + rtt as f64 / 2.0;
+        let offset = server_seconds - now_seconds;
+
+        if !self.is_synced {
+            self.rtt = rtt;
+            self.jitter = 0.0;
+            self.offset = offset;
+            self.is_synced = true;
+            return;
+        }
+        self.jitter += ((rtt - self.rtt).abs() - self.jitter) * JITTER_SMOOTHING;
+        self.rtt += (rtt - self.rtt) * RTT_SMOOTHING;
+        self.offset += (offset - self.offset) * OFFSET_SMOOTHING;
+    }
+
+    /// The tick the server is simulating right now, with the fraction of it that's gone by.
+    pub fn server_tick(&self, tickrate: u32, now_seconds: f64) -> f64 {
+        (now_seconds + self.offset) * tickrate as f64
+    }
+}
+
+/// How much faster (positive) or slower to tick to get from `lead_ticks` to `target_lead_ticks`.
+fn dilation(lead_ticks: f64, target_lead_ticks: f64) -> f64 {
+    ((target_lead_ticks - lead_ticks) * DILATION_PER_TICK).clamp(-MAX_DILATION, MAX_DILATION)
+}
+
+pub fn send_ping_system(
+    time: Res<Time<Real>>,
+    client: Res<QuinnetClient>,
+    mut clock_sync: ResMut<ClockSync>,
+) {
+    let now_seconds = time.elapsed_seconds_f64();
+    if clock_sync
+        .last_ping_seconds
+        .is_some_and(|last_ping_seconds| now_seconds - last_ping_seconds < PING_INTERVAL_SECONDS)
+    {
+        return;
+    }
+    clock_sync.last_ping_seconds = Some(now_seconds);
+
+    if let Err(err) = client.connection().send_message_on(
+        ClientChannels::Pings,
+        ClientMessage::Ping {
+            client_seconds: now_seconds,
+        },
+    ) {
+        shared::bevy::log::error!("Failed to send ping: {:?}", err);
+    }
+}
+
+/// Keeps our ticks ahead of the server's by half a round trip plus the input buffer, so our
+/// inputs arrive right before the server needs them. We get there by running the fixed step a
+/// little faster or slower instead of jumping, which would stutter or drop inputs.
+pub fn time_dilation_system(
+    time: Res<Time<Real>>,
+    server_info: Option<Res<ServerInfo>>,
+    input_controller: Res<PlayerInputController>,
+    mut clock_sync: ResMut<ClockSync>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+) {
+    let Some(server_info) = server_info.filter(|_| clock_sync.is_synced) else {
+        return;
+    };
+    let (Some(acked_input_id), Some(acked_tick)) = (
+        input_controller.last_acked_input_id,
+        input_controller.latest_input.server_tick,
+    ) else {
+        return;
+    };
+    let tickrate = server_info.tickrate;
+
+    // the server takes one input per tick, so from the last ack we know which one it's at now
+    let server_tick = clock_sync.server_tick(tickrate, time.elapsed_seconds_f64());
+    let server_input_id = acked_input_id as f64 + (server_tick - acked_tick as f64);
+    let lead_ticks = input_controller.next_input_id as f64 - server_input_id;
+
+    let buffer_ticks = server_info
+        .input_buffer
+        .map(|input_buffer| input_buffer.target_depth as f64)
+        .unwrap_or_default();
+    let target_lead_ticks =
+        (clock_sync.rtt / 2.0 + clock_sync.jitter * 2.0) as f64 * tickrate as f64 + buffer_ticks;
+
+    clock_sync.dilation = dilation(lead_ticks, target_lead_ticks);
+    fixed_time.set_timestep_hz(tickrate as f64 * (1.0 + clock_sync.dilation));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKRATE: u32 = 64;
+
+    #[test]
+    fn the_first_pong_is_taken_as_is() {
+        let mut clock_sync = ClockSync::default();
+        // sent at 10s, back at 10.1s, the server was on tick 640 (10s) half way through
+        clock_sync.receive_pong(10.0, 640, TICKRATE, 10.1);
+
+        assert!(clock_sync.is_synced);
+        assert!((clock_sync.rtt - 0.1).abs() < 1e-6);
+        assert_eq!(clock_sync.jitter, 0.0);
+        assert!((clock_sync.offset - -0.05).abs() < 1e-9);
+        assert!((clock_sync.server_tick(TICKRATE, 10.1) - 643.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn later_pongs_are_smoothed_in() {
+        let mut clock_sync = ClockSync::default();
+        clock_sync.receive_pong(10.0, 640, TICKRATE, 10.1);
+        // one slow round trip, with the server clock where we expected it
+        clock_sync.receive_pong(20.0, 1280, TICKRATE, 20.3);
+
+        assert!((clock_sync.rtt - (0.1 + 0.2 * RTT_SMOOTHING)).abs() < 1e-6);
+        assert!((clock_sync.jitter - 0.2 * JITTER_SMOOTHING).abs() < 1e-6);
+        // it thinks the server is 0.15s behind now instead of 0.05s, but only moves a bit
+        let offset = -0.05 + (-0.15 - -0.05) * OFFSET_SMOOTHING;
+        assert!((clock_sync.offset - offset).abs() < 1e-6);
+    }
+
+    #[test]
+    fn dilation_follows_the_lead_but_stays_small() {
+        assert_eq!(dilation(4.0, 4.0), 0.0);
+        // too far behind ticks faster, too far ahead slower
+        assert!((dilation(2.0, 4.0) - 2.0 * DILATION_PER_TICK).abs() < 1e-9);
+        assert!((dilation(6.0, 4.0) + 2.0 * DILATION_PER_TICK).abs() < 1e-9);
+        assert_eq!(dilation(0.0, 100.0), MAX_DILATION);
+        assert_eq!(dilation(100.0, 0.0), -MAX_DILATION);
+    }
+}
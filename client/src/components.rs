@@ -2,3 +2,19 @@ use shared::bevy::prelude::*;
 
 #[derive(Component)]
 pub struct LocallyControlled;
+
+/// A projectile we spawned ourselves that the server hasn't confirmed yet.
+#[derive(Component)]
+pub struct PredictedProjectile;
+
+/// A local effect (tracer, muzzle flash) for a shot the server hasn't confirmed yet.
+#[derive(Component)]
+pub struct PredictedShot {
+    pub fired_input_id: u32,
+}
+
+/// A projectile that exists on the server, `server_id` is its id in the tick snapshots.
+#[derive(Component)]
+pub struct ReplicatedProjectile {
+    pub server_id: u64,
+}
@@ -0,0 +1,60 @@
+use shared::bevy::prelude::*;
+use shared::character::CharacterState;
+use shared::gamemode::{MatchState, TEAM_COLORS};
+
+// carried flags float above the carrier's head
+const CARRIED_FLAG_OFFSET: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+
+#[derive(Component)]
+pub struct FlagVisuals {
+    /// Index into `MatchState::flags`.
+    pub index: usize,
+}
+
+/// Keeps a pole for every capture the flag flag, following its carrier when it's taken.
+pub fn update_flags_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    match_state: Option<Res<MatchState>>,
+    characters: Query<(&CharacterState, &Transform), Without<FlagVisuals>>,
+    mut flags: Query<(Entity, &FlagVisuals, &mut Transform)>,
+) {
+    let flag_states = match_state
+        .as_ref()
+        .map(|match_state| match_state.flags.as_slice())
+        .unwrap_or_default();
+
+    for (entity, flag_visuals, mut flag_xform) in flags.iter_mut() {
+        let Some(flag) = flag_states.get(flag_visuals.index) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        let carrier_position = flag.carrier.and_then(|carrier| {
+            characters
+                .iter()
+                .find(|(char_state, _)| char_state.owner_client_id == carrier)
+                .map(|(_, char_xform)| char_xform.translation + CARRIED_FLAG_OFFSET)
+        });
+        flag_xform.translation = carrier_position.unwrap_or(flag.position);
+    }
+
+    let existing = flags.iter().count();
+    for (index, flag) in flag_states.iter().enumerate().skip(existing) {
+        let [r, g, b] = TEAM_COLORS
+            .get(flag.team as usize)
+            .copied()
+            .unwrap_or([1.0, 1.0, 1.0]);
+        commands.spawn((
+            FlagVisuals { index },
+            PbrBundle {
+                mesh: meshes.add(Mesh::from(Cuboid {
+                    half_size: Vec3::new(0.1, 0.75, 0.1),
+                })),
+                material: materials.add(Color::srgb(r, g, b)),
+                transform: Transform::from_translation(flag.position),
+                ..default()
+            },
+        ));
+    }
+}
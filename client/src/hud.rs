@@ -0,0 +1,336 @@
+use crate::components::LocallyControlled;
+use crate::input::PlayerInputController;
+use crate::net::ServerInfo;
+use shared::bevy::prelude::*;
+use shared::character::*;
+use shared::gamemode::{team_name, MatchPhase, MatchState, MatchWinner, PlayerScore};
+use shared::resources::DataAssetHandles;
+use shared::weapons::{get_weapon_config, WeaponConfig, WeaponDryFiredEvent, WeaponInventory};
+use shared::TICKRATE;
+
+// how long the "empty" hint stays up after a dry fire
+const DRY_FIRE_HINT_SECONDS: f32 = 0.5;
+// how long kills stay in the kill feed, and how many are shown at once
+const KILL_FEED_SECONDS: f32 = 5.0;
+const KILL_FEED_LENGTH: usize = 5;
+
+pub struct KillFeedEntry {
+    pub killer_client_id: u64,
+    pub victim_client_id: u64,
+    pub weapon_config_tag: String,
+    /// `Time::elapsed_seconds` when the kill arrived.
+    pub received_at_seconds: f32,
+}
+
+/// Recent kills, newest last.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct KillFeed(Vec<KillFeedEntry>);
+
+#[derive(Component)]
+pub struct HealthText;
+
+#[derive(Component)]
+pub struct MatchText;
+
+#[derive(Component)]
+pub struct KillFeedText;
+
+#[derive(Component)]
+pub struct ScoreboardText;
+
+pub fn setup_hud_system(mut commands: Commands) {
+    commands.spawn((
+        HealthText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 32.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        }),
+    ));
+
+    commands.spawn((
+        MatchText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 28.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        }),
+    ));
+
+    commands.spawn((
+        KillFeedText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 22.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            right: Val::Px(12.0),
+            ..default()
+        }),
+    ));
+
+    commands.spawn((
+        ScoreboardText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 24.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(25.0),
+            left: Val::Percent(30.0),
+            ..default()
+        }),
+    ));
+}
+
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds.ceil() as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub fn update_match_text_system(
+    input_controller: Res<PlayerInputController>,
+    server_info: Option<Res<ServerInfo>>,
+    match_state: Option<Res<MatchState>>,
+    mut match_text: Query<&mut Text, With<MatchText>>,
+) {
+    let Ok(mut text) = match_text.get_single_mut() else {
+        return;
+    };
+    let Some(match_state) = match_state else {
+        text.sections[0].value = String::new();
+        return;
+    };
+
+    let client_id = server_info.as_ref().map(|info| info.client_id);
+    let server_tick = input_controller
+        .latest_input
+        .server_tick
+        .unwrap_or_default();
+    let tickrate = server_info
+        .as_ref()
+        .map(|info| info.tickrate)
+        .unwrap_or(TICKRATE);
+    let time = match_state
+        .seconds_remaining(server_tick, tickrate)
+        .map(format_time)
+        .unwrap_or_default();
+
+    let scores = if match_state.team_scores.is_empty() {
+        let own_score = client_id
+            .and_then(|client_id| match_state.player(client_id))
+            .map(|player| player.score)
+            .unwrap_or_default();
+        let best_score = match_state
+            .players
+            .iter()
+            .map(|player| player.score)
+            .max()
+            .unwrap_or_default();
+        format!("You: {}  Leader: {}", own_score, best_score)
+    } else {
+        match_state
+            .team_scores
+            .iter()
+            .enumerate()
+            .map(|(team, score)| format!("{}: {}", team_name(team as u8), score))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+
+    text.sections[0].value = match match_state.phase {
+        MatchPhase::Warmup if match_state.phase_end_tick.is_none() => {
+            format!("{}  Warmup, waiting for players", match_state.mode.name())
+        }
+        MatchPhase::Warmup => format!("{}  Starting in {}", match_state.mode.name(), time),
+        MatchPhase::Live => format!(
+            "{}  {}  |  {}  (first to {})",
+            match_state.mode.name(),
+            time,
+            scores,
+            match_state.score_limit
+        ),
+        MatchPhase::Intermission => {
+            let result = match match_state.winner {
+                Some(MatchWinner::Player(winner)) if Some(winner) == client_id => {
+                    "You win!".to_string()
+                }
+                Some(MatchWinner::Player(winner)) => {
+                    format!("{} wins!", match_state.username(winner))
+                }
+                Some(MatchWinner::Team(team)) => format!("{} wins!", team_name(team)),
+                Some(MatchWinner::Draw) | None => "Draw!".to_string(),
+            };
+            format!("{}  |  {}  Next match in {}", result, scores, time)
+        }
+    };
+}
+
+pub fn update_health_text_system(
+    time: Res<Time>,
+    mut dry_fire_hint_remaining: Local<f32>,
+    mut dry_fire_events: EventReader<WeaponDryFiredEvent>,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    character: Query<(&CharacterHealth, &WeaponInventory), With<LocallyControlled>>,
+    mut health_text: Query<&mut Text, With<HealthText>>,
+) {
+    if dry_fire_events.read().count() > 0 {
+        *dry_fire_hint_remaining = DRY_FIRE_HINT_SECONDS;
+    }
+    *dry_fire_hint_remaining = (*dry_fire_hint_remaining - time.delta_seconds()).max(0.0);
+
+    if let Ok(mut text) = health_text.get_single_mut() {
+        text.sections[0].value = if let Ok((char_health, inventory)) = character.get_single() {
+            let weapon_text = inventory
+                .active_weapon()
+                .map(|weapon| {
+                    let name = get_weapon_config(
+                        &data_asset_handles,
+                        &weapon_configs,
+                        &weapon.weapon_config_tag,
+                    )
+                    .map(|config| config.name.as_str())
+                    .unwrap_or(&weapon.weapon_config_tag);
+                    let status = if weapon.is_reloading() {
+                        "  (reloading)"
+                    } else if *dry_fire_hint_remaining > 0.0 {
+                        "  (empty)"
+                    } else {
+                        ""
+                    };
+                    format!(
+                        "  {}: {} / {}{}",
+                        name, weapon.magazine, weapon.reserve_ammo, status
+                    )
+                })
+                .unwrap_or_default();
+            format!(
+                "Health: {}  Armor: {}{}",
+                char_health.health, char_health.armor, weapon_text
+            )
+        } else {
+            String::new()
+        };
+    }
+}
+
+pub fn update_kill_feed_system(
+    time: Res<Time>,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    match_state: Option<Res<MatchState>>,
+    mut kill_feed: ResMut<KillFeed>,
+    mut kill_feed_text: Query<&mut Text, With<KillFeedText>>,
+) {
+    let now = time.elapsed_seconds();
+    kill_feed.retain(|entry| now - entry.received_at_seconds < KILL_FEED_SECONDS);
+    let overflow = kill_feed.len().saturating_sub(KILL_FEED_LENGTH);
+    kill_feed.drain(..overflow);
+
+    let Ok(mut text) = kill_feed_text.get_single_mut() else {
+        return;
+    };
+    let username = |client_id: u64| {
+        match_state
+            .as_ref()
+            .map(|match_state| match_state.username(client_id))
+            .unwrap_or_else(|| format!("Player {}", client_id))
+    };
+    text.sections[0].value = kill_feed
+        .iter()
+        .map(|entry| {
+            let weapon_name = get_weapon_config(
+                &data_asset_handles,
+                &weapon_configs,
+                &entry.weapon_config_tag,
+            )
+            .map(|config| config.name.as_str())
+            .unwrap_or(&entry.weapon_config_tag);
+            if entry.killer_client_id == entry.victim_client_id {
+                format!(
+                    "{} [{}] themselves",
+                    username(entry.killer_client_id),
+                    weapon_name
+                )
+            } else {
+                format!(
+                    "{} [{}] {}",
+                    username(entry.killer_client_id),
+                    weapon_name,
+                    username(entry.victim_client_id)
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+}
+
+/// Shows everyone's score while tab is held, best first and split by team.
+pub fn update_scoreboard_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    match_state: Option<Res<MatchState>>,
+    mut scoreboard_text: Query<&mut Text, With<ScoreboardText>>,
+) {
+    let Ok(mut text) = scoreboard_text.get_single_mut() else {
+        return;
+    };
+    let Some(match_state) = match_state.filter(|_| keyboard_input.pressed(KeyCode::Tab)) else {
+        text.sections[0].value = String::new();
+        return;
+    };
+
+    let mut players: Vec<&PlayerScore> = match_state.players.iter().collect();
+    players.sort_by_key(|player| (player.team, std::cmp::Reverse(player.score)));
+
+    let mut lines = vec![format!(
+        "{:<20} {:>6} {:>6} {:>6} {:>6}",
+        "Name", "Score", "Kills", "Deaths", "Ping"
+    )];
+    let mut current_team = None;
+    for player in players {
+        if player.team.is_some() && player.team != current_team {
+            current_team = player.team;
+            let team = player.team.unwrap_or_default();
+            lines.push(format!(
+                "{} ({})",
+                team_name(team),
+                match_state
+                    .team_scores
+                    .get(team as usize)
+                    .copied()
+                    .unwrap_or_default()
+            ));
+        }
+        lines.push(format!(
+            "{:<20} {:>6} {:>6} {:>6} {:>6}",
+            player.username, player.score, player.kills, player.deaths, player.ping_ms
+        ));
+    }
+    text.sections[0].value = lines.join("\n");
+}
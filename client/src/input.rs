@@ -3,13 +3,32 @@ use shared::bevy::prelude::*;
 use shared::character::*;
 use shared::protocol::*;
 
+use crate::chat::ChatState;
 use crate::components::LocallyControlled;
+use crate::interpolation::RenderTick;
+
+const WEAPON_SLOT_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
 
 #[derive(Default, Resource)]
 pub struct PlayerInputController {
     pub latest_input: PlayerInput,
     pub input_history: Vec<PlayerInput>,
     pub next_input_id: u32,
+    /// The newest input the server told us it processed.
+    pub last_acked_input_id: Option<u32>,
+    /// Impulses we predicted for our own character, keyed by the input they were applied after,
+    /// so that replaying inputs during reconciliation reproduces them.
+    pub knockback_history: Vec<(u32, Vec3)>,
 }
 
 impl PlayerInputController {
@@ -21,8 +40,28 @@ impl PlayerInputController {
             .collect::<Vec<PlayerInput>>()
     }
 
-    pub fn get_input(&self, input_id: u32) -> Option<&PlayerInput> {
-        self.input_history.iter().find(|input| input.id == input_id)
+    pub fn knockback_after(&self, input_id: u32) -> Vec3 {
+        self.knockback_history
+            .iter()
+            .filter(|(knockback_input_id, _)| *knockback_input_id == input_id)
+            .map(|(_, impulse)| *impulse)
+            .sum()
+    }
+
+    /// The newest inputs the server hasn't acked yet, at most `count` of them, oldest first.
+    pub fn unacked_inputs(&self, count: usize) -> Vec<PlayerInput> {
+        let unacked = self
+            .input_history
+            .iter()
+            .filter(|input| {
+                self.last_acked_input_id
+                    .is_none_or(|acked_input_id| input.id > acked_input_id)
+            })
+            .collect::<Vec<_>>();
+        unacked[unacked.len().saturating_sub(count)..]
+            .iter()
+            .map(|input| (*input).clone())
+            .collect()
     }
 }
 
@@ -52,17 +91,28 @@ pub fn update_rotation_system(
 
 pub fn update_movement_system(
     mut controller: ResMut<PlayerInputController>,
+    chat: Res<ChatState>,
+    render_tick: Res<RenderTick>,
     mouse_button: Res<ButtonInput<MouseButton>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
 ) {
+    // typing in the chat box doesn't move the character, and clicking while typing doesn't shoot
+    let pressed = |key: KeyCode| !chat.is_typing && keyboard_input.pressed(key);
+
     controller.latest_input.id = controller.next_input_id;
-    controller.latest_input.move_forward = keyboard_input.pressed(KeyCode::KeyW);
-    controller.latest_input.move_backward = keyboard_input.pressed(KeyCode::KeyS);
-    controller.latest_input.move_left = keyboard_input.pressed(KeyCode::KeyA);
-    controller.latest_input.move_right = keyboard_input.pressed(KeyCode::KeyD);
-    controller.latest_input.move_up = keyboard_input.pressed(KeyCode::Space);
-    controller.latest_input.move_down = keyboard_input.pressed(KeyCode::ControlLeft);
-    controller.latest_input.fire = mouse_button.pressed(MouseButton::Left);
+    controller.latest_input.interpolation_tick = render_tick.0.map(|tick| tick.round() as u32);
+    controller.latest_input.move_forward = pressed(KeyCode::KeyW);
+    controller.latest_input.move_backward = pressed(KeyCode::KeyS);
+    controller.latest_input.move_left = pressed(KeyCode::KeyA);
+    controller.latest_input.move_right = pressed(KeyCode::KeyD);
+    controller.latest_input.jump = pressed(KeyCode::Space);
+    controller.latest_input.crouch = pressed(KeyCode::ControlLeft);
+    controller.latest_input.fire = !chat.is_typing && mouse_button.pressed(MouseButton::Left);
+    controller.latest_input.reload = pressed(KeyCode::KeyR);
+    controller.latest_input.switch_weapon = WEAPON_SLOT_KEYS
+        .iter()
+        .position(|key| pressed(*key))
+        .map(|slot| slot as u8);
     controller.next_input_id += 1;
 }
 
@@ -75,4 +125,7 @@ pub fn update_history_system(mut controller: ResMut<PlayerInputController>) {
     controller
         .input_history
         .retain(|input| input.id >= oldest_input_id);
+    controller
+        .knockback_history
+        .retain(|(input_id, _)| *input_id >= oldest_input_id);
 }
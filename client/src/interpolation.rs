@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+
+use shared::bevy::prelude::*;
+
+use crate::clock::ClockSync;
+use crate::net::ServerInfo;
+
+// a second of snapshots is far more than any delay needs
+const MAX_BUFFERED_SNAPSHOTS: usize = shared::TICKRATE as usize;
+
+/// Remote characters are drawn a little in the past, between two snapshots we already have,
+/// instead of guessing ahead of the newest one.
+#[derive(Resource)]
+pub struct InterpolationSettings {
+    /// How far behind the server remote characters are drawn, more survives more lost or late
+    /// snapshots but shows everyone later.
+    pub delay_seconds: f32,
+    /// How far past the newest snapshot we keep moving a character when the buffer runs dry,
+    /// before it stops and waits.
+    pub max_extrapolation_seconds: f32,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        InterpolationSettings {
+            delay_seconds: 0.1,
+            max_extrapolation_seconds: 0.1,
+        }
+    }
+}
+
+/// `INTERPOLATION_DELAY_MS` overrides the default delay.
+pub fn configure_interpolation_system(mut settings: ResMut<InterpolationSettings>) {
+    if let Some(delay_ms) = std::env::var("INTERPOLATION_DELAY_MS")
+        .ok()
+        .and_then(|value| value.parse::<f32>().ok())
+    {
+        settings.delay_seconds = delay_ms.max(0.0) / 1000.0;
+    }
+}
+
+/// The server tick remote characters are drawn at this frame, with the fraction of it.
+#[derive(Resource, Default)]
+pub struct RenderTick(pub Option<f64>);
+
+#[derive(Clone, Copy)]
+struct BufferedSnapshot {
+    tick: u32,
+    position: Vec3,
+    velocity: Vec3,
+}
+
+/// The latest snapshots of a remote character, ordered by tick.
+#[derive(Component, Default)]
+pub struct InterpolationBuffer {
+    snapshots: VecDeque<BufferedSnapshot>,
+}
+
+impl InterpolationBuffer {
+    /// Snapshots arrive unreliably, so late ones are slotted in where they belong.
+    pub fn insert(&mut self, tick: u32, position: Vec3, velocity: Vec3) {
+        let snapshot = BufferedSnapshot {
+            tick,
+            position,
+            velocity,
+        };
+        match self
+            .snapshots
+            .binary_search_by_key(&tick, |snapshot| snapshot.tick)
+        {
+            Ok(index) => self.snapshots[index] = snapshot,
+            Err(index) => self.snapshots.insert(index, snapshot),
+        }
+        while self.snapshots.len() > MAX_BUFFERED_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Where the character was at `tick`, or `None` without any snapshots.
+    pub fn sample(&self, tick: f64, tickrate: u32, max_extrapolation_seconds: f32) -> Option<Vec3> {
+        let oldest = self.snapshots.front()?;
+        let newest = self.snapshots.back()?;
+        if tick <= oldest.tick as f64 {
+            return Some(oldest.position);
+        }
+        if tick >= newest.tick as f64 {
+            let seconds = ((tick - newest.tick as f64) / tickrate as f64) as f32;
+            return Some(
+                newest.position + newest.velocity * seconds.min(max_extrapolation_seconds),
+            );
+        }
+
+        let next_index = self
+            .snapshots
+            .partition_point(|snapshot| (snapshot.tick as f64) <= tick);
+        let from = self.snapshots[next_index - 1];
+        let to = self.snapshots[next_index];
+        let seconds = (to.tick - from.tick) as f32 / tickrate as f32;
+        let t = ((tick - from.tick as f64) / (to.tick - from.tick) as f64) as f32;
+        Some(hermite(from, to, seconds, t))
+    }
+}
+
+/// Cubic hermite between two snapshots `seconds` apart, using their velocities as tangents so
+/// characters curve through turns instead of cutting corners.
+fn hermite(from: BufferedSnapshot, to: BufferedSnapshot, seconds: f32, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    from.position * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + from.velocity * seconds * (t3 - 2.0 * t2 + t)
+        + to.position * (-2.0 * t3 + 3.0 * t2)
+        + to.velocity * seconds * (t3 - t2)
+}
+
+pub fn update_render_tick_system(
+    time: Res<Time<Real>>,
+    settings: Res<InterpolationSettings>,
+    clock_sync: Res<ClockSync>,
+    server_info: Option<Res<ServerInfo>>,
+    mut render_tick: ResMut<RenderTick>,
+) {
+    render_tick.0 = server_info
+        .filter(|_| clock_sync.is_synced)
+        .map(|server_info| {
+            let tickrate = server_info.tickrate as f64;
+            clock_sync.server_tick(server_info.tickrate, time.elapsed_seconds_f64())
+                - settings.delay_seconds as f64 * tickrate
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKRATE: u32 = 10;
+    const MAX_EXTRAPOLATION_SECONDS: f32 = 0.2;
+
+    /// Moving at 1 unit a second along X, so 0.1 a tick.
+    fn moving_buffer(ticks: impl IntoIterator<Item = u32>) -> InterpolationBuffer {
+        let mut buffer = InterpolationBuffer::default();
+        for tick in ticks {
+            buffer.insert(tick, Vec3::X * tick as f32 / TICKRATE as f32, Vec3::X);
+        }
+        buffer
+    }
+
+    fn sample(buffer: &InterpolationBuffer, tick: f64) -> Vec3 {
+        buffer
+            .sample(tick, TICKRATE, MAX_EXTRAPOLATION_SECONDS)
+            .unwrap()
+    }
+
+    #[test]
+    fn nothing_to_sample_without_snapshots() {
+        let buffer = InterpolationBuffer::default();
+        assert!(buffer
+            .sample(10.0, TICKRATE, MAX_EXTRAPOLATION_SECONDS)
+            .is_none());
+    }
+
+    #[test]
+    fn samples_between_snapshots_follow_the_velocities() {
+        // arrives out of order, with a snapshot lost in between
+        let buffer = moving_buffer([10, 12, 11, 14]);
+        assert!(sample(&buffer, 11.0).abs_diff_eq(Vec3::X * 1.1, 1e-5));
+        assert!(sample(&buffer, 12.5).abs_diff_eq(Vec3::X * 1.25, 1e-5));
+        assert!(sample(&buffer, 13.0).abs_diff_eq(Vec3::X * 1.3, 1e-5));
+    }
+
+    #[test]
+    fn samples_curve_through_turns() {
+        let mut buffer = InterpolationBuffer::default();
+        // going +X, then turned to go +Z
+        buffer.insert(0, Vec3::ZERO, Vec3::X * 10.0);
+        buffer.insert(10, Vec3::new(1.0, 0.0, 1.0), Vec3::Z * 10.0);
+
+        let halfway = sample(&buffer, 5.0);
+        let straight_line = Vec3::new(0.5, 0.0, 0.5);
+        // the curve bulges out towards the corner at (1, 0, 0)
+        assert!(halfway.x > straight_line.x);
+        assert!(halfway.z < straight_line.z);
+    }
+
+    #[test]
+    fn samples_before_the_oldest_snapshot_stay_on_it() {
+        let buffer = moving_buffer([10, 11]);
+        assert_eq!(sample(&buffer, 5.0), Vec3::X);
+    }
+
+    #[test]
+    fn samples_past_the_newest_snapshot_extrapolate_only_so_far() {
+        let buffer = moving_buffer([10, 11]);
+        assert!(sample(&buffer, 12.0).abs_diff_eq(Vec3::X * 1.2, 1e-5));
+        // 0.2 seconds at most, so no further than tick 13
+        assert!(sample(&buffer, 20.0).abs_diff_eq(Vec3::X * 1.3, 1e-5));
+    }
+}
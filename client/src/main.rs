@@ -1,40 +1,78 @@
 use input::PlayerInputController;
 use shared::avian3d::prelude::*;
 use shared::bevy::prelude::*;
+use shared::bevy_common_assets::ron::RonAssetPlugin;
 use shared::bevy_quinnet::client::client_connected;
 use shared::bevy_quinnet::client::QuinnetClientPlugin;
+use shared::map::{shared_asset_source, MapConfig, SHARED_ASSET_SOURCE};
 use shared::resources::DataAssetHandles;
+use shared::TICKRATE;
 
 mod character;
+mod chat;
+mod clock;
 pub mod components;
+mod gamemode;
+mod hud;
 mod input;
+mod interpolation;
+mod map;
 mod net;
+mod pickups;
+mod projectiles;
+mod weapons;
 
-pub const TICKRATE: u32 = 64;
 pub const MOUSE_SENISITIVITY: f32 = 0.1;
 
 fn main() {
     App::new()
+        .register_asset_source(SHARED_ASSET_SOURCE, shared_asset_source())
         .add_plugins((
             DefaultPlugins,
             PhysicsPlugins::default(),
             PhysicsDebugPlugin::default(),
             QuinnetClientPlugin::default(),
+            RonAssetPlugin::<MapConfig>::new(&["map.ron"]),
         ))
         //====================================================
         // systems at startup
         //====================================================
-        .add_systems(Startup, (net::start_connection_system, setup_system))
+        .add_systems(
+            Startup,
+            (
+                net::start_connection_system,
+                setup_system,
+                hud::setup_hud_system,
+                chat::setup_chat_system,
+                interpolation::configure_interpolation_system,
+            ),
+        )
         //====================================================
         // systems updating every tick
         //====================================================
         .add_systems(
             Update,
             (
+                map::spawn_map_system,
+                net::verify_content_system,
+                clock::send_ping_system.run_if(client_connected),
+                clock::time_dilation_system,
+                chat::chat_input_system,
                 input::update_rotation_system,
                 character::update_locally_controlled_visuals_system,
+                interpolation::update_render_tick_system,
                 character::update_visuals_system,
                 character::update_camera_system,
+                character::update_weapon_visuals_system,
+                pickups::spin_pickups_system,
+                gamemode::update_flags_system,
+                hud::update_health_text_system,
+                hud::update_match_text_system,
+                hud::update_kill_feed_system,
+                hud::update_scoreboard_system,
+                chat::update_chat_text_system,
+                weapons::update_tracers_system,
+                weapons::update_muzzle_flashes_system,
             )
                 .chain(),
         )
@@ -48,10 +86,13 @@ fn main() {
             (
                 net::handle_client_events_system,
                 net::handle_received_messages_system.run_if(client_connected),
+                shared::character::update_character_colliders_system,
                 input::update_movement_system,
                 input::update_history_system,
                 character::despawn_system,
                 character::move_system,
+                weapons::fire_system,
+                projectiles::simulate_projectiles_system,
                 net::send_input_system.run_if(client_connected),
             )
                 .chain(),
@@ -60,7 +101,14 @@ fn main() {
         // resources
         //====================================================
         .insert_resource(PlayerInputController::default())
+        .init_resource::<clock::ClockSync>()
+        .init_resource::<net::SnapshotBaselines>()
+        .init_resource::<interpolation::InterpolationSettings>()
+        .init_resource::<interpolation::RenderTick>()
+        .init_resource::<weapons::PredictedShots>()
         .init_resource::<DataAssetHandles>()
+        .init_resource::<hud::KillFeed>()
+        .init_resource::<chat::ChatState>()
         //====================================================
         // assets
         //====================================================
@@ -68,15 +116,12 @@ fn main() {
         //====================================================
         // events
         //====================================================
+        .add_event::<shared::weapons::WeaponDryFiredEvent>()
         .add_event::<shared::character::CharacterDespawnEvent>()
         .run();
 }
 
-fn setup_system(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
+fn setup_system(mut commands: Commands) {
     // camera
     commands.spawn(Camera3dBundle {
         transform: Transform::from_translation(Vec3::new(0.0, 1.5, 5.0)),
@@ -87,106 +132,6 @@ fn setup_system(
         ..default()
     });
 
-    // floor
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(Cuboid {
-                half_size: Vec3::new(5.0, 0.5, 5.0),
-            })),
-            material: materials.add(Color::srgb(0.3, 0.5, 0.3)),
-            transform: Transform::from_translation(Vec3::new(0.0, -0.5, 0.0)),
-            ..default()
-        },
-        Collider::cuboid(10.0, 1.0, 10.0),
-        RigidBody::Static,
-    ));
-
-    // wall 1.
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(Cuboid {
-                half_size: Vec3::new(0.5, 2.0, 5.0),
-            })),
-            material: materials.add(Color::srgb(0.5, 0.3, 0.3)),
-            transform: Transform::from_translation(Vec3::new(-5.0, 2.0, 0.0)),
-            ..default()
-        },
-        Collider::cuboid(1.0, 4.0, 10.0),
-        RigidBody::Static,
-    ));
-
-    // wall 2.
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(Cuboid {
-                half_size: Vec3::new(0.5, 2.0, 5.0),
-            })),
-            material: materials.add(Color::srgb(0.3, 0.3, 0.5)),
-            transform: Transform::from_translation(Vec3::new(5.0, 2.0, 0.0)),
-            ..default()
-        },
-        Collider::cuboid(1.0, 4.0, 10.0),
-        RigidBody::Static,
-    ));
-
-    // wall 3.
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(Cuboid {
-                half_size: Vec3::new(5.0, 2.0, 0.5),
-            })),
-            material: materials.add(Color::srgb(0.3, 0.5, 0.5)),
-            transform: Transform::from_translation(Vec3::new(0.0, 2.0, -5.0)),
-            ..default()
-        },
-        Collider::cuboid(10.0, 4.0, 1.0),
-        RigidBody::Static,
-    ));
-
-    // wall 4.
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(Cuboid {
-                half_size: Vec3::new(5.0, 2.0, 0.5),
-            })),
-            material: materials.add(Color::srgb(0.5, 0.5, 0.3)),
-            transform: Transform::from_translation(Vec3::new(0.0, 2.0, 5.0)),
-            ..default()
-        },
-        Collider::cuboid(10.0, 4.0, 1.0),
-        RigidBody::Static,
-    ));
-
-    // pillar 1.
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(Cylinder {
-                radius: 0.5,
-                half_height: 4.0,
-                ..Default::default()
-            })),
-            material: materials.add(Color::srgb(0.5, 0.5, 0.5)),
-            transform: Transform::from_translation(Vec3::new(-1.0, 2.0, -1.0)),
-            ..default()
-        },
-        Collider::cylinder(0.5, 8.0),
-        RigidBody::Static,
-    ));
-
-    // small step
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(Cuboid {
-                half_size: Vec3::new(1.0, 0.1, 1.0),
-            })),
-            material: materials.add(Color::srgb(0.5, 0.5, 0.5)),
-            transform: Transform::from_translation(Vec3::new(1.0, 0.05, 1.0)),
-            ..default()
-        },
-        Collider::cuboid(2.0, 0.2, 2.0),
-        RigidBody::Static,
-    ));
-
     // light
     commands.spawn(PointLightBundle {
         transform: Transform::from_translation(Vec3::new(4.0, 8.0, 4.0)),
@@ -0,0 +1,53 @@
+use shared::bevy::prelude::*;
+use shared::bevy_quinnet::client::QuinnetClient;
+use shared::map::{spawn_map_geometry, CurrentMap, MapConfig};
+
+/// Builds the level the server asked for once our copy of it has loaded.
+///
+/// If our copy collides differently than the server's, prediction would constantly fight the
+/// server's corrections, so we refuse to play on it.
+pub fn spawn_map_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    maps: Res<Assets<MapConfig>>,
+    current_map: Option<ResMut<CurrentMap>>,
+    mut client: ResMut<QuinnetClient>,
+) {
+    let Some(mut current_map) = current_map else {
+        return;
+    };
+    if current_map.is_spawned {
+        return;
+    }
+    let Some(map) = maps.get(&current_map.handle) else {
+        return;
+    };
+
+    let collision_hash = map.collision_hash();
+    if current_map.collision_hash != Some(collision_hash) {
+        shared::bevy::log::error!(
+            "Map {} differs from the server's ({:x} != {:x?}), disconnecting",
+            current_map.name,
+            collision_hash,
+            current_map.collision_hash
+        );
+        commands.remove_resource::<CurrentMap>();
+        if let Err(err) = client.connection_mut().disconnect() {
+            shared::bevy::log::error!("Failed to disconnect: {:?}", err);
+        }
+        return;
+    }
+
+    for geometry in &map.geometry {
+        let entity = spawn_map_geometry(&mut commands, geometry);
+        let [r, g, b] = geometry.color;
+        commands.entity(entity).insert((
+            meshes.add(geometry.shape.mesh()),
+            materials.add(Color::srgb(r, g, b)),
+        ));
+    }
+
+    current_map.is_spawned = true;
+    shared::bevy::log::info!("Spawned map {} ({:x})", map.name, collision_hash);
+}
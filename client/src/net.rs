@@ -2,7 +2,14 @@ use std::thread::sleep;
 use std::time::Duration;
 
 use crate::character::{self, spawn_character};
+use crate::chat::ChatState;
+use crate::clock::ClockSync;
+use crate::hud::{KillFeed, KillFeedEntry};
 use crate::input::PlayerInputController;
+use crate::interpolation::InterpolationBuffer;
+use crate::pickups::apply_pickup_snapshots;
+use crate::projectiles::apply_projectile_snapshots;
+use crate::weapons;
 use shared::bevy::ecs::system::RunSystemOnce;
 use shared::bevy::prelude::*;
 use shared::bevy_quinnet::client::certificate::CertificateVerificationMode;
@@ -10,9 +17,100 @@ use shared::bevy_quinnet::client::connection::{
     ClientEndpointConfiguration, ConnectionEvent, ConnectionFailedEvent,
 };
 use shared::bevy_quinnet::client::QuinnetClient;
-use shared::character::{CharacterDespawnEvent, CharacterState};
-use shared::protocol::{ClientChannels, ClientMessage, ServerMessage};
-use shared::resources::DataAssetHandles;
+use shared::bevy_quinnet::shared::ClientId;
+use shared::character::{
+    CharacterConstants, CharacterDespawnEvent, CharacterHealth, CharacterState,
+};
+use shared::map::CurrentMap;
+use shared::protocol::{
+    content_hash, ClientChannels, ClientMessage, InputBufferHealth, ServerMessage, ServerSettings,
+    TickSnapshot, MAX_REDUNDANT_INPUTS, PROTOCOL_VERSION,
+};
+use shared::resources::{DataAssetHandles, MovementProfile};
+use shared::weapons::{get_weapon_config, WeaponConfig, WeaponInventory};
+use shared::TICKRATE;
+
+/// Full snapshots from the last couple of seconds, the server diffs against whichever one we
+/// acked last.
+#[derive(Resource, Default)]
+pub struct SnapshotBaselines {
+    snapshots: Vec<TickSnapshot>,
+    /// The newest snapshot we applied to the world.
+    applied_tick: Option<u32>,
+}
+
+impl SnapshotBaselines {
+    fn get(&self, tick: u32) -> Option<&TickSnapshot> {
+        self.snapshots.iter().find(|snapshot| snapshot.tick == tick)
+    }
+
+    /// Rebuilds the full snapshot and keeps it as a baseline. Returns what changed since the
+    /// snapshot we applied last along with the full one, or `None` when it's older than that one
+    /// or its baseline is already gone.
+    pub fn receive(&mut self, snapshot: TickSnapshot) -> Option<(TickSnapshot, TickSnapshot)> {
+        let full_snapshot = match snapshot.baseline_tick {
+            Some(baseline_tick) => snapshot.apply_diff(self.get(baseline_tick)?),
+            None => snapshot,
+        };
+        if self.get(full_snapshot.tick).is_none() {
+            self.snapshots.push(full_snapshot.clone());
+        }
+        // the server keeps the same 2 seconds, so it never diffs against anything older
+        let oldest_tick = self
+            .snapshots
+            .iter()
+            .map(|snapshot| snapshot.tick)
+            .max()
+            .unwrap_or_default()
+            .saturating_sub(TICKRATE * 2);
+        self.snapshots
+            .retain(|snapshot| snapshot.tick > oldest_tick);
+
+        if self
+            .applied_tick
+            .is_some_and(|applied_tick| full_snapshot.tick <= applied_tick)
+        {
+            return None;
+        }
+        // the world already has everything up to the applied snapshot
+        let changes = match self
+            .applied_tick
+            .and_then(|applied_tick| self.get(applied_tick))
+        {
+            Some(applied_snapshot) => full_snapshot.diff(applied_snapshot),
+            None => full_snapshot.clone(),
+        };
+        self.applied_tick = Some(full_snapshot.tick);
+        Some((changes, full_snapshot))
+    }
+
+    /// The tick to ack, the server diffs against it. Even when a snapshot couldn't be used this
+    /// moves the server off a baseline we don't have.
+    pub fn ack_tick(&self) -> Option<u32> {
+        self.applied_tick
+    }
+}
+
+/// What the server told us about itself when it let us in.
+#[derive(Resource)]
+pub struct ServerInfo {
+    pub client_id: ClientId,
+    pub tickrate: u32,
+    pub content_hash: u64,
+    pub settings: ServerSettings,
+    /// Set once our map and the weapon configs we received were checked against `content_hash`.
+    pub is_content_verified: bool,
+    /// From the latest snapshot, its target depth is part of how far ahead we run.
+    pub input_buffer: Option<InputBufferHealth>,
+}
+
+impl ServerInfo {
+    /// How much time every tick simulates, the same as on the server. Our fixed step runs a
+    /// little faster or slower to keep up with it, but a tick is always this long.
+    pub fn tick_seconds(&self) -> f32 {
+        1.0 / self.tickrate as f32
+    }
+}
 
 pub fn handle_received_messages_system(world: &mut World) {
     world.resource_scope(|world, mut client: Mut<QuinnetClient>| {
@@ -21,6 +119,65 @@ pub fn handle_received_messages_system(world: &mut World) {
 
         while let Some(message) = endpoint.try_receive_message::<ServerMessage>() {
             match message {
+                // we're in, play by the server's settings
+                (
+                    _channel_id,
+                    ServerMessage::Welcome {
+                        client_id,
+                        tickrate,
+                        content_hash,
+                        settings,
+                    },
+                ) => {
+                    shared::bevy::log::info!(
+                        "Joined {} as client {}: {} on {} at {} ticks per second",
+                        settings.server_name,
+                        client_id,
+                        settings.game_mode.name(),
+                        settings.map,
+                        tickrate
+                    );
+                    world
+                        .resource_mut::<Time<Fixed>>()
+                        .set_timestep_hz(tickrate as f64);
+                    world.insert_resource(ServerInfo {
+                        client_id,
+                        tickrate,
+                        content_hash,
+                        settings,
+                        is_content_verified: false,
+                        input_buffer: None,
+                    });
+                }
+
+                (
+                    _channel_id,
+                    ServerMessage::Pong {
+                        client_seconds,
+                        server_tick,
+                    },
+                ) => {
+                    let now_seconds = world.resource::<Time<Real>>().elapsed_seconds_f64();
+                    let tickrate = world
+                        .get_resource::<ServerInfo>()
+                        .map(|server_info| server_info.tickrate)
+                        .unwrap_or(TICKRATE);
+                    world.resource_mut::<ClockSync>().receive_pong(
+                        client_seconds,
+                        server_tick,
+                        tickrate,
+                        now_seconds,
+                    );
+                }
+
+                (_channel_id, ServerMessage::Rejected(reason)) => {
+                    shared::bevy::log::error!("The server didn't let us in: {}", reason);
+                    if let Err(err) = endpoint.disconnect() {
+                        shared::bevy::log::error!("Failed to disconnect: {:?}", err);
+                    }
+                    break;
+                }
+
                 // we received a list of weapon configs, add them as assets
                 (_channel_id, ServerMessage::WeaponConfig(weapon_config)) => {
                     world.resource_scope(|world, asset_server: Mut<AssetServer>| {
@@ -34,49 +191,130 @@ pub fn handle_received_messages_system(world: &mut World) {
                     });
                 }
 
+                // the server told us which map it's running, load our copy of it
+                (
+                    _channel_id,
+                    ServerMessage::LoadMap {
+                        name,
+                        collision_hash,
+                    },
+                ) => {
+                    shared::bevy::log::info!("Loading map: {}", name);
+                    let current_map = CurrentMap::load(
+                        world.resource::<AssetServer>(),
+                        &name,
+                        Some(collision_hash),
+                    );
+                    world.insert_resource(current_map);
+                }
+
+                // the server picked how characters move this match, predict with the same values
+                (_channel_id, ServerMessage::MovementProfile(constants)) => {
+                    shared::bevy::log::info!("Received movement profile: {:?}", constants);
+                    let mut characters = world.query::<&mut CharacterConstants>();
+                    for mut character_constants in characters.iter_mut(world) {
+                        *character_constants = constants.clone();
+                    }
+                    world.insert_resource(MovementProfile(constants));
+                }
+
+                // the match changed phase, someone scored or a flag moved
+                (_channel_id, ServerMessage::MatchState(match_state)) => {
+                    world.insert_resource(match_state);
+                }
+
+                (_channel_id, ServerMessage::Chat(message)) => {
+                    let elapsed_seconds = world.resource::<Time>().elapsed_seconds();
+                    world
+                        .resource_mut::<ChatState>()
+                        .receive(message, elapsed_seconds);
+                }
+
+                // someone died, show it in the kill feed
+                (
+                    _channel_id,
+                    ServerMessage::Kill {
+                        killer_client_id,
+                        victim_client_id,
+                        weapon_config_tag,
+                    },
+                ) => {
+                    let elapsed_seconds = world.resource::<Time>().elapsed_seconds();
+                    world.resource_mut::<KillFeed>().push(KillFeedEntry {
+                        killer_client_id,
+                        victim_client_id,
+                        weapon_config_tag,
+                        received_at_seconds: elapsed_seconds,
+                    });
+                }
+
+                // someone fired a hitscan weapon, show a tracer for it
+                (_channel_id, ServerMessage::WeaponFiredHitscan(event)) => {
+                    weapons::apply_hitscan_event(world, &event, client_id);
+                }
+
                 // we received a snapshot of the game state
                 (_channel_id, ServerMessage::TickSnapshot(snapshot)) => {
+                    let Some((snapshot, full_snapshot)) =
+                        world.resource_mut::<SnapshotBaselines>().receive(snapshot)
+                    else {
+                        // ack what we do have, or the server keeps diffing against what we don't
+                        let ack_tick = world.resource::<SnapshotBaselines>().ack_tick();
+                        world
+                            .resource_mut::<PlayerInputController>()
+                            .latest_input
+                            .server_tick = ack_tick;
+                        continue;
+                    };
+
                     // query for existing characters
-                    let mut existing_characters =
-                        world.query::<(&mut CharacterState, &mut Transform)>();
+                    let mut existing_characters = world.query::<(
+                        &mut CharacterState,
+                        &mut Transform,
+                        &mut CharacterHealth,
+                        &mut WeaponInventory,
+                        Option<&mut InterpolationBuffer>,
+                    )>();
 
                     for char_snap in &snapshot.characters {
-                        let existing_char_xform_pair = existing_characters
-                            .iter_mut(world)
-                            .find(|(char, _)| char.owner_client_id == char_snap.owner_client_id);
+                        let existing_char_xform_pair =
+                            existing_characters
+                                .iter_mut(world)
+                                .find(|(char, _, _, _, _)| {
+                                    char.owner_client_id == char_snap.owner_client_id
+                                });
 
-                        if let Some((mut existing_char_state, mut existing_char_xform)) =
-                            existing_char_xform_pair
+                        if let Some((
+                            mut existing_char_state,
+                            mut existing_char_xform,
+                            mut existing_char_health,
+                            mut existing_inventory,
+                            mut existing_buffer,
+                        )) = existing_char_xform_pair
                         {
+                            existing_char_health.apply_snapshot(char_snap);
+
                             if existing_char_state.is_locally_controlled(client_id) {
+                                // our prediction can be off even when the server's state didn't
+                                // change, so we reconcile against all of it and not just the changes
+                                let char_snap = full_snapshot
+                                    .characters
+                                    .iter()
+                                    .find(|full_char_snap| {
+                                        full_char_snap.owner_client_id == char_snap.owner_client_id
+                                    })
+                                    .unwrap_or(char_snap);
+                                let predicted_position = existing_char_xform.translation;
                                 existing_char_state
-                                    .apply_snapshot(&char_snap, &mut existing_char_xform);
-                                if char_snap.position.is_none() {
-                                    continue;
-                                }
+                                    .apply_snapshot(char_snap, &mut existing_char_xform);
                                 // we are the owner of this character
                                 // so we need to replay inputs since the last acked input the server has provided us
                                 if let Some(acked_input_id) = snapshot.acked_input_id {
+                                    existing_inventory.apply_snapshot(char_snap);
+
                                     existing_char_state.visuals_offset +=
-                                        char_snap.position.unwrap_or(Vec3::ZERO)
-                                            - existing_char_xform.translation;
-
-                                    // let acked_input = world
-                                    //     .get_resource::<PlayerInputController>()
-                                    //     .and_then(|input_controller| {
-                                    //         input_controller.get_input(acked_input_id)
-                                    //     });
-
-                                    // if let Some(acked_input) = acked_input {
-                                    //     println!(
-                                    //         "{} | {} | {} ",
-                                    //         acked_input.final_position,
-                                    //         char_snap.position.unwrap_or(Vec3::ZERO),
-                                    //         acked_input
-                                    //             .final_position
-                                    //             .distance(char_snap.position.unwrap_or(Vec3::ZERO))
-                                    //     );
-                                    // }
+                                        predicted_position - existing_char_xform.translation;
+
                                     let inputs_to_replay = if let Some(input_controller) =
                                         world.get_resource::<PlayerInputController>()
                                     {
@@ -93,35 +331,85 @@ pub fn handle_received_messages_system(world: &mut World) {
                                         }
 
                                         world.run_system_once(character::move_system);
+                                        world.run_system_once(weapons::replay_fire_system);
                                     }
                                 }
                             } else {
+                                existing_inventory.apply_snapshot(char_snap);
                                 existing_char_state
-                                    .apply_snapshot(&char_snap, &mut existing_char_xform);
+                                    .apply_snapshot(char_snap, &mut existing_char_xform);
+                                if let Some(buffer) = existing_buffer.as_mut() {
+                                    buffer.insert(
+                                        snapshot.tick,
+                                        existing_char_xform.translation,
+                                        existing_char_state.velocity,
+                                    );
+                                }
                             }
                         } else {
-                            world.resource_scope(|world, mut meshes: Mut<Assets<Mesh>>| {
-                                world.resource_scope(
-                                    |world, mut materials: Mut<Assets<StandardMaterial>>| {
-                                        let mut commands = world.commands();
-                                        spawn_character(
-                                            &mut meshes,
-                                            &mut materials,
-                                            &mut commands,
-                                            char_snap.owner_client_id,
-                                            &char_snap.position.unwrap_or(Vec3::ZERO),
-                                            char_snap.owner_client_id == client_id,
-                                        );
-                                        world.flush();
-                                    },
+                            // new characters are spawned with everything we know about them
+                            let char_snap = full_snapshot
+                                .characters
+                                .iter()
+                                .find(|full_char_snap| {
+                                    full_char_snap.owner_client_id == char_snap.owner_client_id
+                                })
+                                .unwrap_or(char_snap);
+                            // characters predict against the level, so wait for it
+                            if !world
+                                .get_resource::<CurrentMap>()
+                                .is_some_and(|current_map| current_map.is_spawned)
+                            {
+                                continue;
+                            }
+                            // characters predict with the server's profile, so wait for it
+                            let Some(constants) = world
+                                .get_resource::<MovementProfile>()
+                                .map(|movement_profile| movement_profile.0.clone())
+                            else {
+                                continue;
+                            };
+                            let entity =
+                                world.resource_scope(|world, mut meshes: Mut<Assets<Mesh>>| {
+                                    world.resource_scope(
+                                        |world, mut materials: Mut<Assets<StandardMaterial>>| {
+                                            let mut commands = world.commands();
+                                            let entity = spawn_character(
+                                                &mut meshes,
+                                                &mut materials,
+                                                &mut commands,
+                                                char_snap.owner_client_id,
+                                                &char_snap.position.unwrap_or(Vec3::ZERO),
+                                                char_snap.yaw.unwrap_or(0.0),
+                                                &constants,
+                                                char_snap.owner_client_id == client_id,
+                                            );
+                                            world.flush();
+                                            entity
+                                        },
+                                    )
+                                });
+
+                            if let Some(mut char_health) = world.get_mut::<CharacterHealth>(entity)
+                            {
+                                char_health.apply_snapshot(char_snap);
+                            }
+                            if let Some(mut inventory) = world.get_mut::<WeaponInventory>(entity) {
+                                inventory.apply_snapshot(char_snap);
+                            }
+                            if let Some(mut buffer) = world.get_mut::<InterpolationBuffer>(entity) {
+                                buffer.insert(
+                                    snapshot.tick,
+                                    char_snap.position.unwrap_or(Vec3::ZERO),
+                                    char_snap.velocity.unwrap_or(Vec3::ZERO),
                                 );
-                            });
+                            }
                         }
                     }
 
                     // handle deletions, any character that isn't in the snapshot should be deleted
                     let mut deletions = Vec::new();
-                    for (char_state, _) in existing_characters.iter(world) {
+                    for (char_state, _, _, _, _) in existing_characters.iter(world) {
                         if snapshot.characters.iter().all(|char_snap| {
                             char_snap.owner_client_id != char_state.owner_client_id
                         }) {
@@ -129,15 +417,25 @@ pub fn handle_received_messages_system(world: &mut World) {
                         }
                     }
                     for client_id in deletions {
-                        world.send_event(CharacterDespawnEvent {
-                            client_id: client_id,
-                        });
+                        world.send_event(CharacterDespawnEvent { client_id });
+                    }
+
+                    apply_projectile_snapshots(world, &snapshot, client_id);
+                    apply_pickup_snapshots(world, &snapshot);
+                    if let Some(acked_input_id) = snapshot.acked_input_id {
+                        weapons::reject_predicted_shots(world, acked_input_id);
                     }
 
                     // Ack the server tick/snapshot!
                     world.resource_scope(|_, mut input_controller: Mut<PlayerInputController>| {
-                        input_controller.latest_input.server_tick = Some(snapshot.tick);
+                        input_controller.latest_input.server_tick = Some(full_snapshot.tick);
+                        if snapshot.acked_input_id.is_some() {
+                            input_controller.last_acked_input_id = snapshot.acked_input_id;
+                        }
                     });
+                    if let Some(mut server_info) = world.get_resource_mut::<ServerInfo>() {
+                        server_info.input_buffer = snapshot.input_buffer;
+                    }
                 }
             }
         }
@@ -151,12 +449,15 @@ pub fn handle_client_events_system(
 ) {
     if !connection_events.is_empty() {
         // We are connected
-        let username: String = "Unnamed Player".into();
+        let username = std::env::var("PLAYER_NAME").unwrap_or("Unnamed Player".into());
         shared::bevy::log::info!("Connected to server. With username: {}", username);
-        if let Err(err) = client
-            .connection()
-            .send_message_on(ClientChannels::Events, ClientMessage::Connect { username })
-        {
+        if let Err(err) = client.connection().send_message_on(
+            ClientChannels::Events,
+            ClientMessage::Connect {
+                username,
+                protocol_version: PROTOCOL_VERSION,
+            },
+        ) {
             shared::bevy::log::error!("Failed to send join message: {:?}", err);
         }
         connection_events.clear();
@@ -180,9 +481,10 @@ pub fn send_input_system(
     client: ResMut<QuinnetClient>,
     input_controller: Res<PlayerInputController>,
 ) {
+    // inputs go out unreliably, so each packet repeats the ones the server might have missed
     if let Err(err) = client.connection().send_message_on(
         ClientChannels::PlayerInputs,
-        ClientMessage::PlayerInput(input_controller.latest_input.clone()),
+        ClientMessage::PlayerInputs(input_controller.unacked_inputs(MAX_REDUNDANT_INPUTS)),
     ) {
         shared::bevy::log::error!("Failed to send input: {:?}", err);
     }
@@ -198,3 +500,73 @@ pub fn on_app_exit_system(app_exit_events: EventReader<AppExit>, client: Res<Qui
         sleep(Duration::from_secs_f32(0.5));
     }
 }
+
+/// Checks our content against the server's once the map is up and every weapon config arrived,
+/// playing with different content would only mispredict.
+pub fn verify_content_system(
+    server_info: Option<ResMut<ServerInfo>>,
+    current_map: Option<Res<CurrentMap>>,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    mut client: ResMut<QuinnetClient>,
+) {
+    let Some(mut server_info) = server_info.filter(|info| !info.is_content_verified) else {
+        return;
+    };
+    let Some(collision_hash) = current_map
+        .filter(|current_map| current_map.is_spawned)
+        .and_then(|current_map| current_map.collision_hash)
+    else {
+        return;
+    };
+    let received_weapon_configs: Option<Vec<&WeaponConfig>> = server_info
+        .settings
+        .weapon_config_tags
+        .iter()
+        .map(|tag| get_weapon_config(&data_asset_handles, &weapon_configs, tag))
+        .collect();
+    let Some(received_weapon_configs) = received_weapon_configs else {
+        return;
+    };
+
+    let our_content_hash = content_hash(collision_hash, &received_weapon_configs);
+    if our_content_hash != server_info.content_hash {
+        shared::bevy::log::error!(
+            "Our content differs from the server's ({:x} != {:x}), disconnecting",
+            our_content_hash,
+            server_info.content_hash
+        );
+        if let Err(err) = client.connection_mut().disconnect() {
+            shared::bevy::log::error!("Failed to disconnect: {:?}", err);
+        }
+    }
+    server_info.is_content_verified = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(tick: u32, baseline_tick: Option<u32>) -> TickSnapshot {
+        TickSnapshot {
+            tick,
+            baseline_tick,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn diffs_against_unknown_baselines_still_ack_what_we_have() {
+        let mut baselines = SnapshotBaselines::default();
+        assert!(baselines.receive(snapshot(10, None)).is_some());
+        assert_eq!(baselines.ack_tick(), Some(10));
+
+        // we never got tick 12, so this can't be rebuilt
+        assert!(baselines.receive(snapshot(14, Some(12))).is_none());
+        assert_eq!(baselines.ack_tick(), Some(10));
+
+        // the server goes back to diffing against what we acked
+        assert!(baselines.receive(snapshot(15, Some(10))).is_some());
+        assert_eq!(baselines.ack_tick(), Some(15));
+    }
+}
@@ -0,0 +1,60 @@
+use shared::bevy::prelude::*;
+use shared::pickups::*;
+use shared::protocol::TickSnapshot;
+
+// pickups spin so they stand out from the level
+const PICKUP_SPIN_SPEED: f32 = 2.0;
+
+pub fn apply_pickup_snapshots(world: &mut World, snapshot: &TickSnapshot) {
+    let mut existing_pickups = world.query::<(&mut WeaponPickup, &mut Visibility)>();
+
+    for pickup_snap in &snapshot.pickups {
+        if let Some((mut pickup, mut visibility)) = existing_pickups
+            .iter_mut(world)
+            .find(|(pickup, _)| pickup.id == pickup_snap.id)
+        {
+            pickup.apply_snapshot(pickup_snap);
+            *visibility = if pickup.is_available() {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+            continue;
+        }
+
+        world.resource_scope(|world, mut meshes: Mut<Assets<Mesh>>| {
+            world.resource_scope(|world, mut materials: Mut<Assets<StandardMaterial>>| {
+                let mut commands = world.commands();
+                let entity = spawn_weapon_pickup(
+                    &mut commands,
+                    pickup_snap.id,
+                    &pickup_snap.weapon_config_tag,
+                    0,
+                    0.0,
+                    pickup_snap.position,
+                );
+                commands.entity(entity).insert((
+                    meshes.add(Mesh::from(Cuboid {
+                        half_size: Vec3::splat(PICKUP_RADIUS * 0.5),
+                    })),
+                    materials.add(Color::srgb(0.9, 0.8, 0.2)),
+                    if pickup_snap.available {
+                        Visibility::Inherited
+                    } else {
+                        Visibility::Hidden
+                    },
+                ));
+                world.flush();
+            });
+        });
+    }
+}
+
+pub fn spin_pickups_system(
+    time: Res<Time>,
+    mut pickups: Query<&mut Transform, With<WeaponPickup>>,
+) {
+    for mut pickup_xform in pickups.iter_mut() {
+        pickup_xform.rotate_y(PICKUP_SPIN_SPEED * time.delta_seconds());
+    }
+}
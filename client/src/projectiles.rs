@@ -0,0 +1,219 @@
+use crate::components::{LocallyControlled, PredictedProjectile, ReplicatedProjectile};
+use crate::input::PlayerInputController;
+use crate::net::ServerInfo;
+use crate::weapons::PredictedShots;
+use shared::avian3d::spatial_query::SpatialQuery;
+use shared::bevy::prelude::*;
+use shared::bevy_quinnet::shared::ClientId;
+use shared::character::{CharacterCollider, CharacterObstacle, CharacterState};
+use shared::projectiles::*;
+use shared::protocol::TickSnapshot;
+use shared::resources::DataAssetHandles;
+use shared::weapons::{get_weapon_config, WeaponConfig};
+
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_projectile(
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    commands: &mut Commands,
+    owner_client_id: ClientId,
+    weapon_config: &WeaponConfig,
+    fired_input_id: u32,
+    origin: Vec3,
+    direction: Vec3,
+) -> Entity {
+    let entity = shared::projectiles::spawn_projectile(
+        commands,
+        owner_client_id,
+        weapon_config,
+        fired_input_id,
+        origin,
+        direction,
+    );
+    commands.entity(entity).insert((
+        meshes.add(Mesh::from(Sphere { radius: 0.15 })),
+        materials.add(Color::srgb(1.0, 0.5, 0.2)),
+    ));
+    entity
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn simulate_projectiles_system(
+    server_info: Option<Res<ServerInfo>>,
+    spatial_query: SpatialQuery,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    mut input_controller: ResMut<PlayerInputController>,
+    mut characters: Query<
+        (
+            &mut CharacterState,
+            &CharacterCollider,
+            &Transform,
+            Has<LocallyControlled>,
+        ),
+        Without<Projectile>,
+    >,
+    mut projectiles: Query<(&mut Projectile, &mut Transform, &mut Visibility)>,
+) {
+    let Some(server_info) = server_info else {
+        return;
+    };
+    let character_obstacles = characters
+        .iter()
+        .map(|(char_state, char_collider, char_xform, _)| {
+            CharacterObstacle::new(
+                char_state.owner_client_id,
+                char_xform.translation,
+                char_collider,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (mut projectile, mut projectile_xform, mut visibility) in projectiles.iter_mut() {
+        let Some(impact) = move_projectile(
+            &spatial_query,
+            &mut projectile,
+            &mut projectile_xform,
+            &character_obstacles,
+            server_info.tick_seconds(),
+        ) else {
+            continue;
+        };
+
+        // predict the knockback our own explosions give us so rocket jumps don't rubber-band
+        let weapon_config = get_weapon_config(
+            &data_asset_handles,
+            &weapon_configs,
+            &projectile.weapon_config_tag,
+        );
+        if let Some(weapon_config) = weapon_config.filter(|config| config.is_explosive()) {
+            for (mut char_state, char_collider, char_xform, locally_controlled) in
+                characters.iter_mut()
+            {
+                if !locally_controlled || char_state.owner_client_id != projectile.owner_client_id {
+                    continue;
+                }
+                let knockback = weapon_config.explosion_knockback(
+                    impact.position(),
+                    char_xform.translation,
+                    char_collider.radius,
+                );
+                if knockback != Vec3::ZERO {
+                    char_state.apply_impulse(knockback);
+                    let input_id = input_controller.latest_input.id;
+                    input_controller
+                        .knockback_history
+                        .push((input_id, knockback));
+                }
+            }
+        }
+
+        // the server decides what actually got hit, we just stop and hide it until it's gone
+        projectile.velocity = Vec3::ZERO;
+        projectile.gravity = 0.0;
+        *visibility = Visibility::Hidden;
+    }
+}
+
+pub fn apply_projectile_snapshots(
+    world: &mut World,
+    snapshot: &TickSnapshot,
+    local_client_id: ClientId,
+) {
+    let mut existing_projectiles = world.query::<(
+        Entity,
+        &mut Projectile,
+        &mut Transform,
+        Option<&ReplicatedProjectile>,
+        Option<&PredictedProjectile>,
+    )>();
+
+    for proj_snap in &snapshot.projectiles {
+        let existing = existing_projectiles.iter_mut(world).find(
+            |(_, projectile, _, replicated, predicted)| {
+                replicated.is_some_and(|replicated| replicated.server_id == proj_snap.id)
+                    || (predicted.is_some()
+                        && projectile.owner_client_id == proj_snap.owner_client_id
+                        && projectile.fired_input_id == proj_snap.fired_input_id)
+            },
+        );
+
+        let existing = existing.map(
+            |(entity, mut projectile, mut projectile_xform, _, predicted)| {
+                // our own projectiles are ahead of the server by as much as our character is,
+                // so we keep simulating them locally instead of pulling them back
+                if predicted.is_none() && projectile.owner_client_id != local_client_id {
+                    projectile.apply_snapshot(proj_snap, &mut projectile_xform);
+                }
+                (entity, predicted.is_some())
+            },
+        );
+
+        if let Some((entity, was_predicted)) = existing {
+            if was_predicted {
+                world
+                    .resource_mut::<PredictedShots>()
+                    .confirm(proj_snap.fired_input_id);
+                world
+                    .entity_mut(entity)
+                    .remove::<PredictedProjectile>()
+                    .insert(ReplicatedProjectile {
+                        server_id: proj_snap.id,
+                    });
+            }
+            continue;
+        }
+
+        let weapon_config = get_weapon_config(
+            world.resource::<DataAssetHandles>(),
+            world.resource::<Assets<WeaponConfig>>(),
+            &proj_snap.weapon_config_tag,
+        )
+        .cloned();
+
+        if let Some(weapon_config) = weapon_config {
+            world.resource_scope(|world, mut meshes: Mut<Assets<Mesh>>| {
+                world.resource_scope(|world, mut materials: Mut<Assets<StandardMaterial>>| {
+                    let mut commands = world.commands();
+                    let entity = spawn_projectile(
+                        &mut meshes,
+                        &mut materials,
+                        &mut commands,
+                        proj_snap.owner_client_id,
+                        &weapon_config,
+                        proj_snap.fired_input_id,
+                        proj_snap.position,
+                        proj_snap.velocity,
+                    );
+                    commands.entity(entity).insert(ReplicatedProjectile {
+                        server_id: proj_snap.id,
+                    });
+                    world.flush();
+                });
+            });
+        }
+    }
+
+    // replicated projectiles the server no longer has are gone, and predicted projectiles
+    // for inputs the server already processed without spawning one were rejected
+    let mut deletions = Vec::new();
+    for (entity, projectile, _, replicated, predicted) in existing_projectiles.iter(world) {
+        let removed_on_server = replicated.is_some_and(|replicated| {
+            snapshot
+                .projectiles
+                .iter()
+                .all(|proj_snap| proj_snap.id != replicated.server_id)
+        });
+        let rejected_by_server = predicted.is_some()
+            && snapshot
+                .acked_input_id
+                .is_some_and(|acked_input_id| projectile.fired_input_id <= acked_input_id);
+
+        if removed_on_server || rejected_by_server {
+            deletions.push(entity);
+        }
+    }
+    for entity in deletions {
+        world.entity_mut(entity).despawn_recursive();
+    }
+}
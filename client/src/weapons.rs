@@ -0,0 +1,294 @@
+use crate::components::{LocallyControlled, PredictedProjectile, PredictedShot};
+use crate::input::PlayerInputController;
+use crate::projectiles::spawn_projectile;
+use shared::avian3d::prelude::*;
+use shared::bevy::prelude::*;
+use shared::bevy_quinnet::shared::ClientId;
+use shared::character::{CharacterConstants, CharacterState};
+use shared::projectiles::Projectile;
+use shared::resources::DataAssetHandles;
+use shared::weapons::{
+    FireMode, WeaponAction, WeaponConfig, WeaponDryFiredEvent, WeaponFiredHitscanEvent,
+    WeaponInventory, MAX_SHOT_DISTANCE,
+};
+
+// how long a hitscan tracer stays visible
+const TRACER_LIFETIME: f32 = 0.1;
+// how long a muzzle flash stays visible
+const MUZZLE_FLASH_LIFETIME: f32 = 0.05;
+// how far in front of the eye the muzzle flash shows up
+const MUZZLE_OFFSET: f32 = 0.5;
+
+#[derive(Component)]
+pub struct Tracer {
+    pub origin: Vec3,
+    pub endpoint: Vec3,
+    pub lifetime_remaining: f32,
+}
+
+impl Tracer {
+    pub fn new(origin: Vec3, endpoint: Vec3) -> Self {
+        Tracer {
+            origin,
+            endpoint,
+            lifetime_remaining: TRACER_LIFETIME,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct MuzzleFlash {
+    pub position: Vec3,
+    pub lifetime_remaining: f32,
+}
+
+impl MuzzleFlash {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        MuzzleFlash {
+            position: origin + direction.normalize_or_zero() * MUZZLE_OFFSET,
+            lifetime_remaining: MUZZLE_FLASH_LIFETIME,
+        }
+    }
+}
+
+/// Inputs we predicted a shot on that the server hasn't confirmed yet.
+#[derive(Resource, Default)]
+pub struct PredictedShots {
+    pub pending: Vec<u32>,
+}
+
+impl PredictedShots {
+    /// Removes the shot, returns true if it was still pending.
+    pub fn confirm(&mut self, fired_input_id: u32) -> bool {
+        let pending_count = self.pending.len();
+        self.pending.retain(|input_id| *input_id != fired_input_id);
+        self.pending.len() != pending_count
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn fire_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    spatial_query: SpatialQuery,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    input_controller: Res<PlayerInputController>,
+    mut predicted_shots: ResMut<PredictedShots>,
+    mut dry_fire_events: EventWriter<WeaponDryFiredEvent>,
+    mut characters: Query<
+        (
+            &CharacterState,
+            &CharacterConstants,
+            &Transform,
+            &mut WeaponInventory,
+        ),
+        With<LocallyControlled>,
+    >,
+) {
+    let input = &input_controller.latest_input;
+    for (char_state, char_constants, char_xform, mut inventory) in characters.iter_mut() {
+        let Some((action, weapon_config)) =
+            inventory.update(input, &data_asset_handles, &weapon_configs)
+        else {
+            continue;
+        };
+
+        match action {
+            WeaponAction::Fired => {}
+            WeaponAction::DryFired => {
+                dry_fire_events.send(WeaponDryFiredEvent {
+                    owner_client_id: char_state.owner_client_id,
+                    weapon_config_tag: weapon_config.tag.clone(),
+                });
+                continue;
+            }
+            WeaponAction::None => continue,
+        }
+
+        // show the shot straight away, the server confirms or rejects it later
+        predicted_shots.pending.push(input.id);
+        let origin = char_state.eye_position(char_constants, char_xform);
+        let direction = input.compute_aim_dir();
+        let fired_input_id = input.id;
+        commands.spawn((
+            MuzzleFlash::new(origin, direction),
+            PredictedShot { fired_input_id },
+        ));
+
+        match weapon_config.fire_mode {
+            FireMode::Hitscan => {
+                // only the world is tested, the server tells us if we hit someone
+                let distance = Dir3::new(direction)
+                    .ok()
+                    .and_then(|direction| {
+                        spatial_query.cast_ray(
+                            origin,
+                            direction,
+                            MAX_SHOT_DISTANCE,
+                            true,
+                            SpatialQueryFilter::default(),
+                        )
+                    })
+                    .map(|hit| hit.time_of_impact)
+                    .unwrap_or(MAX_SHOT_DISTANCE);
+                commands.spawn((
+                    Tracer::new(origin, origin + direction * distance),
+                    PredictedShot { fired_input_id },
+                ));
+            }
+            FireMode::Projectile => {
+                let entity = spawn_projectile(
+                    &mut meshes,
+                    &mut materials,
+                    &mut commands,
+                    char_state.owner_client_id,
+                    weapon_config,
+                    fired_input_id,
+                    origin,
+                    direction,
+                );
+                commands.entity(entity).insert(PredictedProjectile);
+            }
+        }
+    }
+}
+
+/// Replays the weapons for an input during reconciliation.
+///
+/// Nothing new is spawned here, but shots we predicted that no longer happen are cancelled.
+#[allow(clippy::too_many_arguments)]
+pub fn replay_fire_system(
+    mut commands: Commands,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    input_controller: Res<PlayerInputController>,
+    mut predicted_shots: ResMut<PredictedShots>,
+    mut characters: Query<&mut WeaponInventory, With<LocallyControlled>>,
+    shot_effects: Query<(Entity, &PredictedShot)>,
+    predicted_projectiles: Query<(Entity, &Projectile), With<PredictedProjectile>>,
+) {
+    let input = &input_controller.latest_input;
+    for mut inventory in characters.iter_mut() {
+        let action = inventory
+            .update(input, &data_asset_handles, &weapon_configs)
+            .map(|(action, _)| action);
+
+        if action != Some(WeaponAction::Fired) && predicted_shots.confirm(input.id) {
+            shared::bevy::log::debug!("Cancelled predicted shot for input {}", input.id);
+            shot_effects
+                .iter()
+                .filter(|(_, shot)| shot.fired_input_id == input.id)
+                .for_each(|(entity, _)| commands.entity(entity).despawn());
+            predicted_projectiles
+                .iter()
+                .filter(|(_, projectile)| projectile.fired_input_id == input.id)
+                .for_each(|(entity, _)| commands.entity(entity).despawn_recursive());
+        }
+    }
+}
+
+/// Shows a hitscan shot from the server, our own predicted shots are corrected instead.
+pub fn apply_hitscan_event(
+    world: &mut World,
+    event: &WeaponFiredHitscanEvent,
+    local_client_id: ClientId,
+) {
+    if event.owner_client_id == local_client_id
+        && world
+            .resource_mut::<PredictedShots>()
+            .confirm(event.fired_input_id)
+    {
+        // the server knows who we hit, so move our tracer's endpoint if it's still showing
+        let mut tracers = world.query::<(Entity, &mut Tracer, &PredictedShot)>();
+        let confirmed = tracers
+            .iter_mut(world)
+            .filter(|(_, _, shot)| shot.fired_input_id == event.fired_input_id)
+            .map(|(entity, mut tracer, _)| {
+                tracer.endpoint = event.endpoint;
+                entity
+            })
+            .collect::<Vec<_>>();
+        for entity in confirmed {
+            world.entity_mut(entity).remove::<PredictedShot>();
+        }
+        return;
+    }
+
+    let direction = event.endpoint - event.origin;
+    world.spawn(MuzzleFlash::new(event.origin, direction));
+    world.spawn(Tracer::new(event.origin, event.endpoint));
+}
+
+/// Removes the effects of predicted shots for inputs the server processed without confirming.
+pub fn reject_predicted_shots(world: &mut World, acked_input_id: u32) {
+    let mut rejected = Vec::new();
+    world
+        .resource_mut::<PredictedShots>()
+        .pending
+        .retain(|fired_input_id| {
+            if *fired_input_id <= acked_input_id {
+                rejected.push(*fired_input_id);
+                false
+            } else {
+                true
+            }
+        });
+    if rejected.is_empty() {
+        return;
+    }
+    shared::bevy::log::debug!("Server rejected predicted shots: {:?}", rejected);
+
+    let mut shot_effects = world.query::<(Entity, &PredictedShot)>();
+    let deletions = shot_effects
+        .iter(world)
+        .filter(|(_, shot)| rejected.contains(&shot.fired_input_id))
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+    for entity in deletions {
+        world.entity_mut(entity).despawn();
+    }
+}
+
+pub fn update_tracers_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut gizmos: Gizmos,
+    mut tracers: Query<(Entity, &mut Tracer)>,
+) {
+    for (entity, mut tracer) in tracers.iter_mut() {
+        let alpha = (tracer.lifetime_remaining / TRACER_LIFETIME).clamp(0.0, 1.0);
+        gizmos.line(
+            tracer.origin,
+            tracer.endpoint,
+            Color::srgba(1.0, 0.9, 0.5, alpha),
+        );
+
+        tracer.lifetime_remaining -= time.delta_seconds();
+        if tracer.lifetime_remaining <= 0.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn update_muzzle_flashes_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut gizmos: Gizmos,
+    mut muzzle_flashes: Query<(Entity, &mut MuzzleFlash)>,
+) {
+    for (entity, mut muzzle_flash) in muzzle_flashes.iter_mut() {
+        gizmos.sphere(
+            muzzle_flash.position,
+            Quat::IDENTITY,
+            0.1,
+            Color::srgb(1.0, 0.8, 0.3),
+        );
+
+        muzzle_flash.lifetime_remaining -= time.delta_seconds();
+        if muzzle_flash.lifetime_remaining <= 0.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
@@ -1,57 +1,142 @@
+use std::collections::HashSet;
+
 use shared::avian3d::spatial_query::SpatialQuery;
 use shared::bevy::prelude::*;
+use shared::bevy_quinnet::shared::ClientId;
 use shared::character::*;
 use shared::resources::DataAssetHandles;
-use shared::weapons::{get_weapon_config, WeaponConfig, WeaponState};
+use shared::weapons::{
+    FireMode, WeaponAction, WeaponConfig, WeaponDryFiredEvent, WeaponFiredProjectileEvent,
+    WeaponInventory, MAX_SHOT_DISTANCE,
+};
 
-use crate::net::Application;
+use crate::events::LagCompensatedShotEvent;
+use crate::net::{Application, ServerConfig};
 
+#[allow(clippy::too_many_arguments)]
 pub fn consume_input_system(
     fixed_time: Res<Time<Fixed>>,
+    server_config: Res<ServerConfig>,
     data_asset_handles: Res<DataAssetHandles>,
     weapon_configs: Res<Assets<WeaponConfig>>,
-    mut spatial_query: SpatialQuery,
+    spatial_query: SpatialQuery,
     mut game_server: ResMut<Application>,
+    mut shot_events: EventWriter<LagCompensatedShotEvent>,
+    mut projectile_events: EventWriter<WeaponFiredProjectileEvent>,
+    mut dry_fire_events: EventWriter<WeaponDryFiredEvent>,
     mut characters: Query<(
         &mut CharacterState,
+        &mut CharacterCollider,
         &mut Transform,
         &CharacterConstants,
-        &mut WeaponState,
+        &mut WeaponInventory,
     )>,
 ) {
-    for (mut char_state, mut char_xform, char_constants, mut weapon_state) in characters.iter_mut()
+    // everyone collides with where the others were before this tick's moves
+    let obstacles = characters
+        .iter()
+        .map(|(char_state, char_collider, char_xform, _, _)| {
+            CharacterObstacle::new(
+                char_state.owner_client_id,
+                char_xform.translation,
+                char_collider,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (mut char_state, mut char_collider, mut char_xform, char_constants, mut inventory) in
+        characters.iter_mut()
     {
-        if let Some(client_info) = game_server
+        let Some(client_info) = game_server
             .clients
             .iter_mut()
             .find(|c| c.client_id == char_state.owner_client_id)
-        {
-            if let Some(input_to_process) = &client_info.input_to_process {
-                move_character(
-                    input_to_process.compute_wish_dir(),
-                    &mut spatial_query,
-                    &mut char_state,
-                    &mut char_xform,
-                    char_constants,
-                    fixed_time.delta_seconds(),
-                );
+        else {
+            continue;
+        };
+        let popped_input = client_info
+            .input_queue
+            .pop(server_config.input_buffer_depth);
+        // without a new input the character carries on instead of hanging where it is
+        let Some(input_to_process) = popped_input
+            .clone()
+            .or_else(|| client_info.input_queue.stand_in())
+        else {
+            continue;
+        };
 
-                if input_to_process.fire && weapon_state.can_fire(&fixed_time) {
-                    let weapon_config = get_weapon_config(
-                        &data_asset_handles,
-                        &weapon_configs,
-                        &weapon_state.weapon_config_tag,
-                    );
+        move_character(
+            &input_to_process,
+            &spatial_query,
+            &obstacles,
+            &mut char_state,
+            &mut char_collider,
+            &mut char_xform,
+            char_constants,
+            fixed_time.delta_seconds(),
+        );
 
-                    if let Some(weapon_config) = weapon_config {
-                        weapon_state.on_fire(&fixed_time, weapon_config);
-                        println!("Firing weapon!");
+        // stand-ins don't fire, and their id already went by on the weapon clock
+        if popped_input.is_none() {
+            continue;
+        }
+        if let Some((action, weapon_config)) =
+            inventory.update(&input_to_process, &data_asset_handles, &weapon_configs)
+        {
+            match action {
+                WeaponAction::Fired => match weapon_config.fire_mode {
+                    FireMode::Hitscan => {
+                        shot_events.send(LagCompensatedShotEvent {
+                            shooter_client_id: char_state.owner_client_id,
+                            weapon_config_tag: weapon_config.tag.clone(),
+                            fired_input_id: input_to_process.id,
+                            view_tick: input_to_process
+                                .interpolation_tick
+                                .or(input_to_process.server_tick),
+                            origin: char_state.eye_position(char_constants, &char_xform),
+                            direction: input_to_process.compute_aim_dir(),
+                            max_distance: MAX_SHOT_DISTANCE,
+                        });
+                    }
+                    FireMode::Projectile => {
+                        projectile_events.send(WeaponFiredProjectileEvent {
+                            owner_client_id: char_state.owner_client_id,
+                            weapon_config_tag: weapon_config.tag.clone(),
+                            fired_input_id: input_to_process.id,
+                            origin: char_state.eye_position(char_constants, &char_xform),
+                            direction: input_to_process.compute_aim_dir(),
+                        });
                     }
+                },
+                WeaponAction::DryFired => {
+                    dry_fire_events.send(WeaponDryFiredEvent {
+                        owner_client_id: char_state.owner_client_id,
+                        weapon_config_tag: weapon_config.tag.clone(),
+                    });
                 }
-
-                client_info.server_last_processed_input_id = Some(input_to_process.id);
+                WeaponAction::None => {}
             }
         }
+
+        client_info.server_last_processed_input_id = Some(input_to_process.id);
+    }
+
+    // without a character there's nothing to simulate, but the queue still moves on every tick
+    let character_owners: HashSet<ClientId> = characters
+        .iter()
+        .map(|(char_state, _, _, _, _)| char_state.owner_client_id)
+        .collect();
+    for client_info in game_server
+        .clients
+        .iter_mut()
+        .filter(|c| !character_owners.contains(&c.client_id))
+    {
+        if let Some(input) = client_info
+            .input_queue
+            .pop(server_config.input_buffer_depth)
+        {
+            client_info.server_last_processed_input_id = Some(input.id);
+        }
     }
 }
 
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use shared::bevy::prelude::*;
+use shared::bevy_quinnet::server::{Endpoint, QuinnetServer};
+use shared::bevy_quinnet::shared::ClientId;
+use shared::chat::{sanitize_chat_message, ChatMessage, ChatSender};
+use shared::protocol::{ServerChannels, ServerMessage};
+
+use crate::events::{AnnouncementEvent, ClientChatEvent, ClientConnectedEvent};
+use crate::net::{Application, ClientInfo};
+
+// players can send this many messages in a row, then one more per second
+const CHAT_BURST: f32 = 5.0;
+const CHAT_MESSAGES_PER_SECOND: f32 = 1.0;
+
+/// How many messages each client may still send right now.
+#[derive(Resource, Default)]
+pub struct ChatAllowances(HashMap<ClientId, f32>);
+
+impl ChatAllowances {
+    fn refill(&mut self, delta_seconds: f32) {
+        for allowance in self.0.values_mut() {
+            *allowance = (*allowance + delta_seconds * CHAT_MESSAGES_PER_SECOND).min(CHAT_BURST);
+        }
+    }
+
+    /// Takes one message from the client's allowance, `false` if it has none left.
+    fn spend(&mut self, client_id: ClientId) -> bool {
+        let allowance = self.0.entry(client_id).or_insert(CHAT_BURST);
+        if *allowance < 1.0 {
+            return false;
+        }
+        *allowance -= 1.0;
+        true
+    }
+}
+
+/// The team a message goes to, `None` for everyone. Without teams team chat has nobody to go
+/// to, so it goes to everyone.
+fn chat_team(team_only: bool, sender_team: Option<u8>) -> Option<u8> {
+    sender_team.filter(|_| team_only)
+}
+
+fn chat_recipients(clients: &[ClientInfo], team: Option<u8>) -> Vec<ClientId> {
+    clients
+        .iter()
+        .filter(|c| team.is_none() || c.team == team)
+        .map(|c| c.client_id)
+        .collect()
+}
+
+fn send_chat(endpoint: &mut Endpoint, recipients: &[ClientId], message: ChatMessage) {
+    if let Err(err) = endpoint.send_group_message_on(
+        recipients.iter(),
+        ServerChannels::GameEvents,
+        ServerMessage::Chat(message),
+    ) {
+        shared::bevy::log::error!("Failed to send chat message: {}", err);
+    }
+}
+
+pub fn chat_system(
+    fixed_time: Res<Time<Fixed>>,
+    app: Res<Application>,
+    mut allowances: ResMut<ChatAllowances>,
+    mut server: ResMut<QuinnetServer>,
+    mut chat_events: EventReader<ClientChatEvent>,
+) {
+    allowances
+        .0
+        .retain(|client_id, _| app.clients.iter().any(|c| c.client_id == *client_id));
+    allowances.refill(fixed_time.delta_seconds());
+
+    let endpoint = server.endpoint_mut();
+    for event in chat_events.read() {
+        let Some(sender) = app.clients.iter().find(|c| c.client_id == event.client_id) else {
+            continue;
+        };
+        let Some(text) = sanitize_chat_message(&event.text) else {
+            continue;
+        };
+
+        if !allowances.spend(event.client_id) {
+            send_chat(
+                endpoint,
+                &[event.client_id],
+                ChatMessage {
+                    sender: ChatSender::Server,
+                    text: "You are sending messages too fast".to_string(),
+                    team_only: false,
+                },
+            );
+            continue;
+        }
+
+        let team = chat_team(event.team_only, sender.team);
+        let team_only = team.is_some();
+        let recipients = chat_recipients(&app.clients, team);
+        shared::bevy::log::info!(
+            "{}{}: {}",
+            sender.username,
+            if team_only { " (team)" } else { "" },
+            text
+        );
+        send_chat(
+            endpoint,
+            &recipients,
+            ChatMessage {
+                sender: ChatSender::Player {
+                    client_id: sender.client_id,
+                    username: sender.username.clone(),
+                    team: sender.team,
+                },
+                text,
+                team_only,
+            },
+        );
+    }
+}
+
+pub fn announce_joins_system(
+    mut connected_events: EventReader<ClientConnectedEvent>,
+    mut announcement_events: EventWriter<AnnouncementEvent>,
+) {
+    for event in connected_events.read() {
+        announcement_events.send(AnnouncementEvent {
+            text: format!("{} joined", event.username),
+        });
+    }
+}
+
+pub fn announcement_system(
+    app: Res<Application>,
+    mut server: ResMut<QuinnetServer>,
+    mut announcement_events: EventReader<AnnouncementEvent>,
+) {
+    let endpoint = server.endpoint_mut();
+    let clients = app.client_ids();
+    for event in announcement_events.read() {
+        shared::bevy::log::info!("Announcement: {}", event.text);
+        send_chat(
+            endpoint,
+            &clients,
+            ChatMessage {
+                sender: ChatSender::Server,
+                text: event.text.clone(),
+                team_only: false,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(client_id: ClientId, team: Option<u8>) -> ClientInfo {
+        ClientInfo {
+            client_id,
+            username: format!("player {}", client_id),
+            input_queue: default(),
+            client_last_acked_tick: None,
+            server_last_processed_input_id: None,
+            team,
+        }
+    }
+
+    #[test]
+    fn a_burst_is_allowed_then_one_message_a_second() {
+        let mut allowances = ChatAllowances::default();
+        for _ in 0..CHAT_BURST as usize {
+            assert!(allowances.spend(1));
+        }
+        assert!(!allowances.spend(1));
+        // others have their own allowance
+        assert!(allowances.spend(2));
+
+        allowances.refill(0.5 / CHAT_MESSAGES_PER_SECOND);
+        assert!(!allowances.spend(1));
+        allowances.refill(0.5 / CHAT_MESSAGES_PER_SECOND);
+        assert!(allowances.spend(1));
+        assert!(!allowances.spend(1));
+
+        // waiting doesn't save up more than a burst
+        allowances.refill(100.0);
+        for _ in 0..CHAT_BURST as usize {
+            assert!(allowances.spend(1));
+        }
+        assert!(!allowances.spend(1));
+    }
+
+    #[test]
+    fn team_chat_goes_to_the_team_or_to_everyone_without_teams() {
+        let teams = [client(1, Some(0)), client(2, Some(1)), client(3, Some(0))];
+        assert_eq!(
+            chat_recipients(&teams, chat_team(true, Some(0))),
+            vec![1, 3]
+        );
+        assert_eq!(
+            chat_recipients(&teams, chat_team(false, Some(0))),
+            vec![1, 2, 3]
+        );
+
+        let free_for_all = [client(1, None), client(2, None)];
+        assert_eq!(chat_team(true, None), None);
+        assert_eq!(
+            chat_recipients(&free_for_all, chat_team(true, None)),
+            vec![1, 2]
+        );
+    }
+}
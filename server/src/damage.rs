@@ -0,0 +1,294 @@
+use shared::bevy::prelude::*;
+use shared::bevy_quinnet::shared::ClientId;
+use shared::character::*;
+use shared::resources::DataAssetHandles;
+use shared::weapons::{get_weapon_config, WeaponConfig};
+
+use crate::events::{
+    CharacterDeathEvent, DamageEvent, LagCompensatedHitEvent, ProjectileImpactEvent,
+};
+use crate::spawning::SpawnProtection;
+
+pub fn hit_damage_system(
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    mut hit_events: EventReader<LagCompensatedHitEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    for hit in hit_events.read() {
+        let Some(victim_client_id) = hit.victim_client_id else {
+            continue;
+        };
+        shared::bevy::log::debug!(
+            "Client {} hit {} with {} (rewound to tick {})",
+            hit.shooter_client_id,
+            victim_client_id,
+            hit.weapon_config_tag,
+            hit.rewound_tick
+        );
+
+        if let Some(weapon_config) =
+            get_weapon_config(&data_asset_handles, &weapon_configs, &hit.weapon_config_tag)
+        {
+            damage_events.send(DamageEvent {
+                attacker_client_id: hit.shooter_client_id,
+                victim_client_id,
+                weapon_config_tag: hit.weapon_config_tag.clone(),
+                amount: weapon_config.damage,
+            });
+        }
+    }
+}
+
+/// The knockback and damage an explosion gives a character, `None` when it doesn't reach them.
+fn explosion_effect(
+    weapon_config: &WeaponConfig,
+    impact: &ProjectileImpactEvent,
+    client_id: ClientId,
+    position: Vec3,
+    radius: f32,
+) -> Option<(Vec3, u32)> {
+    // a direct hit always takes the full blast
+    let scale = if impact.direct_hit_client_id == Some(client_id) {
+        1.0
+    } else {
+        weapon_config.explosion_scale(impact.position, position, radius)
+    };
+    if scale <= 0.0 {
+        return None;
+    }
+
+    let mut amount = weapon_config.damage as f32 * scale;
+    if client_id == impact.owner_client_id {
+        amount *= weapon_config.self_damage_multiplier;
+    }
+    let knockback = weapon_config.scaled_knockback(impact.position, position, scale);
+    Some((knockback, amount.round() as u32))
+}
+
+pub fn projectile_impact_system(
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    mut characters: Query<(&mut CharacterState, &CharacterCollider, &Transform)>,
+    mut impact_events: EventReader<ProjectileImpactEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    for impact in impact_events.read() {
+        let Some(weapon_config) = get_weapon_config(
+            &data_asset_handles,
+            &weapon_configs,
+            &impact.weapon_config_tag,
+        ) else {
+            continue;
+        };
+
+        if !weapon_config.is_explosive() {
+            if let Some(victim_client_id) = impact.direct_hit_client_id {
+                damage_events.send(DamageEvent {
+                    attacker_client_id: impact.owner_client_id,
+                    victim_client_id,
+                    weapon_config_tag: impact.weapon_config_tag.clone(),
+                    amount: weapon_config.damage,
+                });
+            }
+            continue;
+        }
+
+        for (mut char_state, char_collider, char_xform) in characters.iter_mut() {
+            let Some((knockback, amount)) = explosion_effect(
+                weapon_config,
+                impact,
+                char_state.owner_client_id,
+                char_xform.translation,
+                char_collider.radius,
+            ) else {
+                continue;
+            };
+
+            char_state.apply_impulse(knockback);
+            if amount > 0 {
+                damage_events.send(DamageEvent {
+                    attacker_client_id: impact.owner_client_id,
+                    victim_client_id: char_state.owner_client_id,
+                    weapon_config_tag: impact.weapon_config_tag.clone(),
+                    amount,
+                });
+            }
+        }
+    }
+}
+
+pub fn apply_damage_system(
+    mut characters: Query<(&CharacterState, &mut CharacterHealth, Has<SpawnProtection>)>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut death_events: EventWriter<CharacterDeathEvent>,
+) {
+    for event in damage_events.read() {
+        if let Some((_, mut char_health, is_protected)) = characters
+            .iter_mut()
+            .find(|(char_state, _, _)| char_state.owner_client_id == event.victim_client_id)
+        {
+            if is_protected {
+                continue;
+            }
+            if char_health.take_damage(event.amount) {
+                death_events.send(CharacterDeathEvent {
+                    killer_client_id: event.attacker_client_id,
+                    victim_client_id: event.victim_client_id,
+                    weapon_config_tag: event.weapon_config_tag.clone(),
+                });
+            }
+        }
+    }
+}
+
+pub fn death_system(
+    mut death_events: EventReader<CharacterDeathEvent>,
+    mut character_despawn_events: EventWriter<CharacterDespawnEvent>,
+) {
+    for event in death_events.read() {
+        shared::bevy::log::info!(
+            "Client {} was killed by {} with {}",
+            event.victim_client_id,
+            event.killer_client_id,
+            event.weapon_config_tag
+        );
+        character_despawn_events.send(CharacterDespawnEvent {
+            client_id: event.victim_client_id,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::bevy::ecs::system::RunSystemOnce;
+    use shared::weapons::{ExplosionFalloff, FireMode};
+
+    const SHOOTER: ClientId = 1;
+    const VICTIM: ClientId = 2;
+
+    fn rocket_launcher() -> WeaponConfig {
+        WeaponConfig {
+            tag: "rocket_launcher".into(),
+            name: "Rocket Launcher".into(),
+            fire_mode: FireMode::Projectile,
+            fire_rate_ms: 800,
+            switch_time_ms: 0,
+            damage: 100,
+            magazine_size: 4,
+            reload_time_ms: 0,
+            max_ammo: 20,
+            projectile_speed: 20.0,
+            projectile_gravity: 0.0,
+            explosion_radius: 4.0,
+            explosion_falloff: ExplosionFalloff::Linear,
+            self_damage_multiplier: 0.5,
+            knockback_impulse: 10.0,
+        }
+    }
+
+    fn impact(direct_hit_client_id: Option<ClientId>) -> ProjectileImpactEvent {
+        ProjectileImpactEvent {
+            owner_client_id: SHOOTER,
+            weapon_config_tag: "rocket_launcher".into(),
+            position: Vec3::ZERO,
+            direct_hit_client_id,
+        }
+    }
+
+    #[test]
+    fn explosions_fall_off_towards_the_edge() {
+        let config = rocket_launcher();
+        // measured to the edge of the character, so this is halfway out
+        let (knockback, amount) =
+            explosion_effect(&config, &impact(None), VICTIM, Vec3::X * 2.5, 0.5).unwrap();
+        assert_eq!(amount, 50);
+        assert!(knockback.abs_diff_eq(Vec3::X * 5.0, 1e-5));
+
+        let quadratic = WeaponConfig {
+            explosion_falloff: ExplosionFalloff::Quadratic,
+            ..rocket_launcher()
+        };
+        let (knockback, amount) =
+            explosion_effect(&quadratic, &impact(None), VICTIM, Vec3::X * 2.5, 0.5).unwrap();
+        assert_eq!(amount, 25);
+        assert!(knockback.abs_diff_eq(Vec3::X * 2.5, 1e-5));
+    }
+
+    #[test]
+    fn direct_hits_take_the_full_blast() {
+        let (knockback, amount) = explosion_effect(
+            &rocket_launcher(),
+            &impact(Some(VICTIM)),
+            VICTIM,
+            Vec3::X * 3.0,
+            0.5,
+        )
+        .unwrap();
+        assert_eq!(amount, 100);
+        assert!(knockback.abs_diff_eq(Vec3::X * 10.0, 1e-5));
+    }
+
+    #[test]
+    fn own_explosions_hurt_less_but_push_as_hard() {
+        let (knockback, amount) =
+            explosion_effect(&rocket_launcher(), &impact(None), SHOOTER, Vec3::Y, 0.0).unwrap();
+        assert_eq!(amount, 38);
+        assert!(knockback.abs_diff_eq(Vec3::Y * 7.5, 1e-5));
+    }
+
+    #[test]
+    fn explosions_out_of_reach_do_nothing() {
+        let config = rocket_launcher();
+        assert!(explosion_effect(&config, &impact(None), VICTIM, Vec3::X * 5.0, 0.5).is_none());
+        assert_eq!(
+            config.scaled_knockback(Vec3::ZERO, Vec3::X, 0.0),
+            Vec3::ZERO
+        );
+    }
+
+    #[test]
+    fn spawn_protection_blocks_damage() {
+        let mut world = World::new();
+        world.init_resource::<Events<DamageEvent>>();
+        world.init_resource::<Events<CharacterDeathEvent>>();
+        let character = |owner_client_id| CharacterState {
+            owner_client_id,
+            velocity: Vec3::ZERO,
+            visuals_offset: Vec3::ZERO,
+            is_grounded: true,
+            is_crouching: false,
+        };
+        let health = || CharacterHealth {
+            health: 100,
+            armor: 0,
+        };
+        let protected = world
+            .spawn((
+                character(SHOOTER),
+                health(),
+                SpawnProtection {
+                    remaining_seconds: 1.0,
+                },
+            ))
+            .id();
+        let unprotected = world.spawn((character(VICTIM), health())).id();
+
+        for victim_client_id in [SHOOTER, VICTIM] {
+            world.send_event(DamageEvent {
+                attacker_client_id: 3,
+                victim_client_id,
+                weapon_config_tag: "rifle".into(),
+                amount: 40,
+            });
+        }
+        world.run_system_once(apply_damage_system);
+
+        assert_eq!(world.get::<CharacterHealth>(protected).unwrap().health, 100);
+        assert_eq!(
+            world.get::<CharacterHealth>(unprotected).unwrap().health,
+            60
+        );
+    }
+}
@@ -1,5 +1,13 @@
 use shared::{bevy::prelude::*, protocol::PlayerInput};
 
+/// A client asked to join, it isn't let in until the request is checked.
+#[derive(Event)]
+pub struct ClientConnectRequestEvent {
+    pub client_id: u64,
+    pub username: String,
+    pub protocol_version: u32,
+}
+
 #[derive(Event)]
 pub struct ClientConnectedEvent {
     pub client_id: u64,
@@ -11,8 +19,70 @@ pub struct ClientDisconnectedEvent {
     pub client_id: u64,
 }
 
+#[derive(Event)]
+pub struct ClientChatEvent {
+    pub client_id: u64,
+    pub text: String,
+    pub team_only: bool,
+}
+
+/// A chat message from the server itself, sent to everyone.
+#[derive(Event)]
+pub struct AnnouncementEvent {
+    pub text: String,
+}
+
 #[derive(Event)]
 pub struct ClientInputEvent {
     pub client_id: u64,
-    pub input: PlayerInput,
+    /// Oldest first, repeats inputs that were already sent in case those got lost.
+    pub inputs: Vec<PlayerInput>,
+}
+
+/// A shot that needs to be tested against characters as the shooter saw them.
+#[derive(Event)]
+pub struct LagCompensatedShotEvent {
+    pub shooter_client_id: u64,
+    pub weapon_config_tag: String,
+    pub fired_input_id: u32,
+    /// The tick the shooter saw the others at, if they told us.
+    pub view_tick: Option<u32>,
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub max_distance: f32,
+}
+
+/// The result of a [`LagCompensatedShotEvent`] after rewinding the other characters.
+#[derive(Event)]
+pub struct LagCompensatedHitEvent {
+    pub shooter_client_id: u64,
+    pub victim_client_id: Option<u64>,
+    pub weapon_config_tag: String,
+    pub fired_input_id: u32,
+    pub rewound_tick: u32,
+    pub origin: Vec3,
+    pub endpoint: Vec3,
+}
+
+#[derive(Event)]
+pub struct DamageEvent {
+    pub attacker_client_id: u64,
+    pub victim_client_id: u64,
+    pub weapon_config_tag: String,
+    pub amount: u32,
+}
+
+#[derive(Event)]
+pub struct CharacterDeathEvent {
+    pub killer_client_id: u64,
+    pub victim_client_id: u64,
+    pub weapon_config_tag: String,
+}
+
+#[derive(Event)]
+pub struct ProjectileImpactEvent {
+    pub owner_client_id: u64,
+    pub weapon_config_tag: String,
+    pub position: Vec3,
+    pub direct_hit_client_id: Option<u64>,
 }
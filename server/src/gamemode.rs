@@ -1,11 +1,580 @@
-use crate::events::ClientConnectedEvent;
-use shared::{bevy::prelude::*, character::spawn_character};
+use crate::events::{AnnouncementEvent, CharacterDeathEvent, ClientConnectedEvent};
+use crate::net::Application;
+use crate::spawning::SpawnState;
+use shared::{
+    bevy::prelude::*,
+    bevy_quinnet::shared::ClientId,
+    character::{CharacterDespawnEvent, CharacterSpawnEvent, CharacterState},
+    gamemode::{team_name, GameModeKind, MatchPhase, MatchState, MatchWinner, PlayerScore},
+    map::{spawn_map_geometry, CurrentMap, MapConfig},
+    pickups::spawn_weapon_pickup,
+    TICKRATE,
+};
 
-pub fn handle_client_connected_system(
+mod capture_the_flag;
+mod deathmatch;
+mod team_deathmatch;
+
+/// The rules of a match: how teams are made, what scores and who wins.
+///
+/// The match lifecycle (warmup, live, intermission) and the time limit are shared by every mode
+/// and handled by the systems below, modes only see what happens while the match is live.
+pub trait GameMode: Send + Sync + 'static {
+    fn kind(&self) -> GameModeKind;
+
+    /// How many teams players are split into, 0 for free-for-all.
+    fn team_count(&self) -> u8 {
+        0
+    }
+
+    /// Used when the server isn't given a `SCORE_LIMIT`.
+    fn default_score_limit(&self) -> u32;
+
+    /// Sets up the mode's own state when the match goes live.
+    fn start(&mut self, _state: &mut MatchState, _map: &MapConfig) {}
+
+    /// Scores a kill, `killer` and `victim` are the same for suicides.
+    fn on_kill(&mut self, state: &mut MatchState, killer: ClientId, victim: ClientId);
+
+    /// Runs every tick of a live match with the position of every character.
+    fn update(
+        &mut self,
+        _state: &mut MatchState,
+        _characters: &[(ClientId, Vec3)],
+        _delta_seconds: f32,
+    ) {
+    }
+
+    /// Who won, once someone reached the score limit or the time ran out.
+    fn winner(&self, state: &MatchState, time_is_up: bool) -> Option<MatchWinner> {
+        let scores: Vec<(MatchWinner, i32)> = if self.team_count() > 0 {
+            (0..self.team_count())
+                .map(|team| {
+                    let score = state.team_scores.get(team as usize).copied();
+                    (MatchWinner::Team(team), score.unwrap_or_default())
+                })
+                .collect()
+        } else {
+            state
+                .players
+                .iter()
+                .map(|player| (MatchWinner::Player(player.client_id), player.score))
+                .collect()
+        };
+        winner_by_score(&scores, state.score_limit, time_is_up)
+    }
+}
+
+/// The highest score wins once it reaches `score_limit` or the time is up, a tie for the lead
+/// when time runs out is a draw.
+fn winner_by_score(
+    scores: &[(MatchWinner, i32)],
+    score_limit: u32,
+    time_is_up: bool,
+) -> Option<MatchWinner> {
+    let Some(best) = scores.iter().map(|(_, score)| *score).max() else {
+        return time_is_up.then_some(MatchWinner::Draw);
+    };
+    if best < score_limit as i32 && !time_is_up {
+        return None;
+    }
+
+    let mut leaders = scores.iter().filter(|(_, score)| *score == best);
+    match (leaders.next(), leaders.next()) {
+        (Some((leader, _)), None) => Some(*leader),
+        _ if time_is_up => Some(MatchWinner::Draw),
+        _ => None,
+    }
+}
+
+fn seconds_to_ticks(seconds: f32) -> u32 {
+    (seconds * TICKRATE as f32).round() as u32
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct ActiveGameMode(Box<dyn GameMode>);
+
+impl ActiveGameMode {
+    pub fn new(kind: GameModeKind) -> Self {
+        ActiveGameMode(match kind {
+            GameModeKind::Deathmatch => Box::new(deathmatch::Deathmatch),
+            GameModeKind::TeamDeathmatch => Box::new(team_deathmatch::TeamDeathmatch),
+            GameModeKind::CaptureTheFlag => Box::<capture_the_flag::CaptureTheFlag>::default(),
+        })
+    }
+}
+
+#[derive(Resource)]
+pub struct MatchSettings {
+    /// Name of the map in `shared/assets/maps`.
+    pub map: String,
+    /// Tag of the `*.character.ron` profile characters move with.
+    pub movement_profile: String,
+    pub game_mode: GameModeKind,
+    /// Overrides the game mode's own score limit.
+    pub score_limit: Option<u32>,
+    pub time_limit_seconds: f32,
+    /// The warmup countdown only starts once this many players are in.
+    pub min_players: usize,
+    pub warmup_seconds: f32,
+    pub intermission_seconds: f32,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        MatchSettings {
+            map: "arena".to_string(),
+            movement_profile: "default".to_string(),
+            game_mode: GameModeKind::Deathmatch,
+            score_limit: None,
+            time_limit_seconds: 600.0,
+            min_players: 2,
+            warmup_seconds: 10.0,
+            intermission_seconds: 10.0,
+        }
+    }
+}
+
+/// Sets up the match, `MAP`, `MOVEMENT_PROFILE`, `GAME_MODE`, `SCORE_LIMIT` and `TIME_LIMIT`
+/// override the defaults.
+pub fn configure_match_system(mut commands: Commands, mut match_settings: ResMut<MatchSettings>) {
+    if let Ok(map) = std::env::var("MAP") {
+        match_settings.map = map;
+    }
+    if let Ok(tag) = std::env::var("MOVEMENT_PROFILE") {
+        match_settings.movement_profile = tag;
+    }
+    if let Ok(game_mode) = std::env::var("GAME_MODE") {
+        match game_mode.as_str() {
+            "dm" | "deathmatch" => match_settings.game_mode = GameModeKind::Deathmatch,
+            "tdm" | "team_deathmatch" => match_settings.game_mode = GameModeKind::TeamDeathmatch,
+            "ctf" | "capture_the_flag" => match_settings.game_mode = GameModeKind::CaptureTheFlag,
+            _ => shared::bevy::log::warn!("Unknown game mode: {}", game_mode),
+        }
+    }
+    if let Some(score_limit) = std::env::var("SCORE_LIMIT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        match_settings.score_limit = Some(score_limit);
+    }
+    if let Some(time_limit) = std::env::var("TIME_LIMIT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        match_settings.time_limit_seconds = time_limit;
+    }
+
+    let game_mode = ActiveGameMode::new(match_settings.game_mode);
+    commands.insert_resource(MatchState {
+        mode: game_mode.kind(),
+        score_limit: match_settings
+            .score_limit
+            .unwrap_or(game_mode.default_score_limit()),
+        team_scores: vec![0; game_mode.team_count() as usize],
+        ..default()
+    });
+    commands.insert_resource(game_mode);
+
+    shared::bevy::log::info!(
+        "Playing {} on {} with movement profile: {}",
+        match_settings.game_mode.name(),
+        match_settings.map,
+        match_settings.movement_profile
+    );
+}
+
+pub fn load_map_system(
     mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    match_settings: Res<MatchSettings>,
+) {
+    commands.insert_resource(CurrentMap::load(&asset_server, &match_settings.map, None));
+}
+
+/// Builds the level and its pickups once the map has loaded.
+pub fn spawn_map_system(
+    mut commands: Commands,
+    maps: Res<Assets<MapConfig>>,
+    mut current_map: ResMut<CurrentMap>,
+) {
+    if current_map.is_spawned {
+        return;
+    }
+    let Some(map) = maps.get(&current_map.handle) else {
+        return;
+    };
+
+    for geometry in &map.geometry {
+        spawn_map_geometry(&mut commands, geometry);
+    }
+    for pickup in &map.pickups {
+        spawn_weapon_pickup(
+            &mut commands,
+            pickup.id,
+            &pickup.weapon_config_tag,
+            pickup.ammo,
+            pickup.respawn_seconds,
+            pickup.position,
+        );
+    }
+
+    current_map.collision_hash = Some(map.collision_hash());
+    current_map.is_spawned = true;
+    shared::bevy::log::info!("Spawned map {} ({:x})", map.name, map.collision_hash());
+}
+
+/// Puts new players on the smallest team and spawns them.
+pub fn handle_client_connected_system(
+    mut app: ResMut<Application>,
+    game_mode: Res<ActiveGameMode>,
+    mut match_state: ResMut<MatchState>,
     mut client_connected_events: EventReader<ClientConnectedEvent>,
+    mut spawn_events: EventWriter<CharacterSpawnEvent>,
 ) {
     for event in client_connected_events.read() {
-        spawn_character(&mut commands, event.client_id, &Vec3::new(0.0, 2.0, 0.0));
+        let team = (0..game_mode.team_count()).min_by_key(|team| {
+            match_state
+                .players
+                .iter()
+                .filter(|player| player.team == Some(*team))
+                .count()
+        });
+        if let Some(client) = app
+            .clients
+            .iter_mut()
+            .find(|c| c.client_id == event.client_id)
+        {
+            client.team = team;
+        }
+        match_state.players.push(PlayerScore {
+            client_id: event.client_id,
+            username: event.username.clone(),
+            team,
+            score: 0,
+            kills: 0,
+            deaths: 0,
+            ping_ms: 0,
+        });
+
+        spawn_events.send(CharacterSpawnEvent {
+            client_id: event.client_id,
+        });
+    }
+}
+
+pub fn score_system(
+    mut game_mode: ResMut<ActiveGameMode>,
+    mut match_state: ResMut<MatchState>,
+    mut death_events: EventReader<CharacterDeathEvent>,
+) {
+    for event in death_events.read() {
+        if match_state.phase != MatchPhase::Live {
+            continue;
+        }
+        if let Some(victim) = match_state.player_mut(event.victim_client_id) {
+            victim.deaths += 1;
+        }
+        game_mode.on_kill(
+            &mut match_state,
+            event.killer_client_id,
+            event.victim_client_id,
+        );
+    }
+}
+
+/// Moves the match from warmup to live to intermission and back.
+#[allow(clippy::too_many_arguments)]
+pub fn match_phase_system(
+    fixed_time: Res<Time<Fixed>>,
+    app: Res<Application>,
+    match_settings: Res<MatchSettings>,
+    maps: Res<Assets<MapConfig>>,
+    current_map: Res<CurrentMap>,
+    mut game_mode: ResMut<ActiveGameMode>,
+    mut match_state: ResMut<MatchState>,
+    mut spawn_state: ResMut<SpawnState>,
+    characters: Query<(&CharacterState, &Transform)>,
+    mut despawn_events: EventWriter<CharacterDespawnEvent>,
+    mut announcement_events: EventWriter<AnnouncementEvent>,
+) {
+    let tick = app.current_tick();
+    let phase_is_over = match_state
+        .phase_end_tick
+        .is_some_and(|end_tick| tick >= end_tick);
+
+    // players that left don't count anymore
+    if match_state
+        .players
+        .iter()
+        .any(|player| app.clients.iter().all(|c| c.client_id != player.client_id))
+    {
+        match_state
+            .players
+            .retain(|player| app.clients.iter().any(|c| c.client_id == player.client_id));
+    }
+
+    match match_state.phase {
+        MatchPhase::Warmup => {
+            if match_state.players.len() < match_settings.min_players {
+                if match_state.phase_end_tick.is_some() {
+                    match_state.phase_end_tick = None;
+                }
+            } else if match_state.phase_end_tick.is_none() {
+                match_state.phase_end_tick =
+                    Some(tick + seconds_to_ticks(match_settings.warmup_seconds));
+            } else if phase_is_over {
+                let Some(map) = maps.get(&current_map.handle) else {
+                    return;
+                };
+                match_state.reset_scores(game_mode.team_count());
+                game_mode.start(&mut match_state, map);
+                match_state.phase = MatchPhase::Live;
+                match_state.phase_end_tick =
+                    Some(tick + seconds_to_ticks(match_settings.time_limit_seconds));
+
+                // everyone starts the match fresh from a spawn point
+                for (char_state, _) in characters.iter() {
+                    despawn_events.send(CharacterDespawnEvent {
+                        client_id: char_state.owner_client_id,
+                    });
+                    spawn_state.queue_respawn(char_state.owner_client_id, 0.0);
+                }
+                announcement_events.send(AnnouncementEvent {
+                    text: format!(
+                        "{} started, first to {}",
+                        match_state.mode.name(),
+                        match_state.score_limit
+                    ),
+                });
+            }
+        }
+        MatchPhase::Live => {
+            let positions: Vec<(ClientId, Vec3)> = characters
+                .iter()
+                .map(|(char_state, char_xform)| {
+                    (char_state.owner_client_id, char_xform.translation)
+                })
+                .collect();
+            game_mode.update(&mut match_state, &positions, fixed_time.delta_seconds());
+
+            if let Some(winner) = game_mode.winner(&match_state, phase_is_over) {
+                match_state.winner = Some(winner);
+                match_state.phase = MatchPhase::Intermission;
+                match_state.phase_end_tick =
+                    Some(tick + seconds_to_ticks(match_settings.intermission_seconds));
+                let text = match winner {
+                    MatchWinner::Player(client_id) => {
+                        format!("{} wins the match", match_state.username(client_id))
+                    }
+                    MatchWinner::Team(team) => format!("{} wins the match", team_name(team)),
+                    MatchWinner::Draw => "The match is a draw".to_string(),
+                };
+                announcement_events.send(AnnouncementEvent { text });
+            }
+        }
+        MatchPhase::Intermission => {
+            if phase_is_over {
+                match_state.phase = MatchPhase::Warmup;
+                match_state.phase_end_tick = None;
+                match_state.flags.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ClientInfo;
+    use crate::spawning;
+    use shared::character::spawn_character;
+    use shared::map::SpawnPoint;
+    use shared::resources::{DataAssetHandles, MovementProfile};
+    use shared::weapons::{WeaponConfig, WeaponInventory};
+
+    const CLIENT_IDS: [ClientId; 2] = [1, 2];
+
+    /// A live match with a player for each of `teams`, with client ids counting up from 1.
+    pub(super) fn live_match(teams: &[Option<u8>], team_count: u8) -> MatchState {
+        MatchState {
+            phase: MatchPhase::Live,
+            score_limit: 10,
+            players: teams
+                .iter()
+                .enumerate()
+                .map(|(index, &team)| PlayerScore {
+                    client_id: index as ClientId + 1,
+                    username: format!("player {}", index + 1),
+                    team,
+                    score: 0,
+                    kills: 0,
+                    deaths: 0,
+                    ping_ms: 0,
+                })
+                .collect(),
+            team_scores: vec![0; team_count as usize],
+            ..default()
+        }
+    }
+
+    #[test]
+    fn the_leader_wins_at_the_score_limit() {
+        let scores = [(MatchWinner::Player(1), 9), (MatchWinner::Player(2), 4)];
+        assert_eq!(winner_by_score(&scores, 10, false), None);
+
+        let scores = [(MatchWinner::Player(1), 10), (MatchWinner::Player(2), 4)];
+        assert_eq!(
+            winner_by_score(&scores, 10, false),
+            Some(MatchWinner::Player(1))
+        );
+    }
+
+    #[test]
+    fn the_leader_wins_when_time_is_up() {
+        let scores = [(MatchWinner::Team(0), 2), (MatchWinner::Team(1), 3)];
+        assert_eq!(
+            winner_by_score(&scores, 10, true),
+            Some(MatchWinner::Team(1))
+        );
+    }
+
+    #[test]
+    fn ties_play_on_until_time_is_up() {
+        let scores = [(MatchWinner::Player(1), 10), (MatchWinner::Player(2), 10)];
+        assert_eq!(winner_by_score(&scores, 10, false), None);
+        assert_eq!(winner_by_score(&scores, 10, true), Some(MatchWinner::Draw));
+        assert_eq!(winner_by_score(&[], 10, false), None);
+        assert_eq!(winner_by_score(&[], 10, true), Some(MatchWinner::Draw));
+    }
+
+    fn warmup_world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Time<Fixed>>();
+        world.init_resource::<Events<CharacterSpawnEvent>>();
+        world.init_resource::<Events<CharacterDespawnEvent>>();
+        world.init_resource::<Events<AnnouncementEvent>>();
+        world.init_resource::<DataAssetHandles>();
+        world.init_resource::<Assets<WeaponConfig>>();
+        world.init_resource::<MovementProfile>();
+        world.init_resource::<SpawnState>();
+        world.insert_resource(MatchSettings::default());
+        world.insert_resource(ActiveGameMode::new(GameModeKind::Deathmatch));
+
+        let mut maps = Assets::<MapConfig>::default();
+        let handle = maps.add(MapConfig {
+            name: "test".to_string(),
+            geometry: Vec::new(),
+            spawn_points: [Vec3::new(-5.0, 1.0, 0.0), Vec3::new(5.0, 1.0, 0.0)]
+                .into_iter()
+                .map(|position| SpawnPoint {
+                    position,
+                    yaw: 0.0,
+                    team: None,
+                })
+                .collect(),
+            pickups: Vec::new(),
+            flag_bases: Vec::new(),
+        });
+        world.insert_resource(maps);
+        world.insert_resource(CurrentMap {
+            name: "test".to_string(),
+            handle,
+            collision_hash: None,
+            is_spawned: true,
+        });
+
+        let mut app = Application::with_snapshots(100, Vec::new());
+        let mut match_state = MatchState {
+            // the warmup countdown just ran out
+            phase_end_tick: Some(100),
+            score_limit: 10,
+            ..default()
+        };
+        for client_id in CLIENT_IDS {
+            app.clients.push(ClientInfo {
+                client_id,
+                username: format!("player {}", client_id),
+                input_queue: default(),
+                client_last_acked_tick: None,
+                server_last_processed_input_id: None,
+                team: None,
+            });
+            match_state.players.push(PlayerScore {
+                client_id,
+                username: format!("player {}", client_id),
+                team: None,
+                score: 3,
+                kills: 3,
+                deaths: 0,
+                ping_ms: 0,
+            });
+        }
+        world.insert_resource(app);
+        world.insert_resource(match_state);
+        world
+    }
+
+    fn characters(world: &mut World) -> Vec<(Entity, ClientId)> {
+        world
+            .query::<(Entity, &CharacterState)>()
+            .iter(world)
+            .map(|(entity, char_state)| (entity, char_state.owner_client_id))
+            .collect()
+    }
+
+    #[test]
+    fn everyone_respawns_when_the_match_goes_live() {
+        let mut world = warmup_world();
+        let mut tick = Schedule::default();
+        tick.add_systems(
+            (
+                match_phase_system,
+                spawning::respawn_timer_system,
+                spawning::spawn_system,
+                crate::characters::despawn_system,
+            )
+                .chain(),
+        );
+
+        // everyone's been running around in warmup
+        let mut commands = world.commands();
+        let warmup_characters: Vec<Entity> = CLIENT_IDS
+            .into_iter()
+            .map(|client_id| {
+                spawn_character(
+                    &mut commands,
+                    client_id,
+                    &Vec3::ZERO,
+                    0.0,
+                    &default(),
+                    WeaponInventory::default(),
+                )
+            })
+            .collect();
+        world.flush();
+
+        tick.run(&mut world);
+        assert_eq!(world.resource::<MatchState>().phase, MatchPhase::Live);
+        assert!(world
+            .resource::<MatchState>()
+            .players
+            .iter()
+            .all(|player| player.score == 0));
+        assert!(characters(&mut world).is_empty());
+
+        tick.run(&mut world);
+        let mut characters = characters(&mut world);
+        characters.sort_by_key(|(_, client_id)| *client_id);
+        assert_eq!(
+            characters
+                .iter()
+                .map(|(_, client_id)| *client_id)
+                .collect::<Vec<_>>(),
+            CLIENT_IDS
+        );
+        assert!(characters
+            .iter()
+            .all(|(entity, _)| !warmup_characters.contains(entity)));
     }
 }
@@ -0,0 +1,253 @@
+use shared::bevy::prelude::*;
+use shared::bevy_quinnet::shared::ClientId;
+use shared::gamemode::{team_name, FlagState, GameModeKind, MatchState};
+use shared::map::MapConfig;
+
+use super::GameMode;
+
+// how close a character has to get to a flag to take, return or capture it
+const FLAG_TOUCH_DISTANCE: f32 = 1.0;
+// dropped flags go back to their base after this long
+const FLAG_RETURN_SECONDS: f32 = 20.0;
+// player score for a capture, kills are worth one
+const CAPTURE_POINTS: i32 = 5;
+
+/// The first character touching `flag`, from the other team if `is_enemy` or else its own.
+fn touching_flag(
+    state: &MatchState,
+    characters: &[(ClientId, Vec3)],
+    flag: &FlagState,
+    is_enemy: bool,
+) -> Option<ClientId> {
+    characters
+        .iter()
+        .find(|(client_id, position)| {
+            position.distance(flag.position) < FLAG_TOUCH_DISTANCE
+                && state
+                    .team_of(*client_id)
+                    .is_some_and(|team| (team != flag.team) == is_enemy)
+        })
+        .map(|(client_id, _)| *client_id)
+}
+
+/// Two teams, each with a flag. Taking the enemy flag back to your own base while your flag is
+/// home scores for the team. Kills only count towards the player's score.
+#[derive(Default)]
+pub struct CaptureTheFlag {
+    /// Seconds until each dropped flag returns, indexed like `MatchState::flags`.
+    return_timers: Vec<f32>,
+    /// Where each carried flag last was, indexed like `MatchState::flags`. The match state only
+    /// gets it once the flag is dropped, it's sent to everyone reliably every time it changes.
+    carried_positions: Vec<Vec3>,
+}
+
+impl GameMode for CaptureTheFlag {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::CaptureTheFlag
+    }
+
+    fn team_count(&self) -> u8 {
+        2
+    }
+
+    fn default_score_limit(&self) -> u32 {
+        3
+    }
+
+    fn start(&mut self, state: &mut MatchState, map: &MapConfig) {
+        state.flags = map
+            .flag_bases
+            .iter()
+            .map(|base| FlagState::new(base.team, base.position))
+            .collect();
+        self.return_timers = vec![0.0; state.flags.len()];
+        self.carried_positions = state.flags.iter().map(|flag| flag.position).collect();
+    }
+
+    fn on_kill(&mut self, state: &mut MatchState, killer: ClientId, victim: ClientId) {
+        let victim_team = state.team_of(victim);
+        let Some(killer_score) = state.player_mut(killer) else {
+            return;
+        };
+        if killer != victim && killer_score.team != victim_team {
+            killer_score.score += 1;
+            killer_score.kills += 1;
+        } else {
+            killer_score.score -= 1;
+        }
+    }
+
+    fn update(
+        &mut self,
+        state: &mut MatchState,
+        characters: &[(ClientId, Vec3)],
+        delta_seconds: f32,
+    ) {
+        for flag_index in 0..state.flags.len() {
+            let flag = state.flags[flag_index].clone();
+
+            if let Some(carrier) = flag.carrier {
+                let Some(&(_, position)) = characters
+                    .iter()
+                    .find(|(client_id, _)| *client_id == carrier)
+                else {
+                    // the carrier died or left, the flag stays where they last were
+                    state.flags[flag_index].position = self.carried_positions[flag_index];
+                    state.flags[flag_index].carrier = None;
+                    self.return_timers[flag_index] = FLAG_RETURN_SECONDS;
+                    shared::bevy::log::info!("{} flag dropped", team_name(flag.team));
+                    continue;
+                };
+                // clients draw a carried flag on its carrier, so there's no need to send this
+                self.carried_positions[flag_index] = position;
+
+                // captured by bringing it to the carrier's own flag while that one is home
+                let carrier_team = state.team_of(carrier);
+                let is_capture = state.flags.iter().any(|own_flag| {
+                    Some(own_flag.team) == carrier_team
+                        && own_flag.is_home()
+                        && own_flag.home.distance(position) < FLAG_TOUCH_DISTANCE
+                });
+                if let (true, Some(carrier_team)) = (is_capture, carrier_team) {
+                    state.flags[flag_index].return_home();
+                    state.add_team_score(carrier_team, 1);
+                    if let Some(carrier_score) = state.player_mut(carrier) {
+                        carrier_score.score += CAPTURE_POINTS;
+                    }
+                    shared::bevy::log::info!(
+                        "Client {} captured the {} flag",
+                        carrier,
+                        team_name(flag.team)
+                    );
+                }
+                continue;
+            }
+
+            if let Some(client_id) = touching_flag(state, characters, &flag, true) {
+                state.flags[flag_index].carrier = Some(client_id);
+                shared::bevy::log::info!(
+                    "Client {} took the {} flag",
+                    client_id,
+                    team_name(flag.team)
+                );
+                continue;
+            }
+            if !flag.is_home() {
+                if let Some(client_id) = touching_flag(state, characters, &flag, false) {
+                    state.flags[flag_index].return_home();
+                    shared::bevy::log::info!(
+                        "Client {} returned the {} flag",
+                        client_id,
+                        team_name(flag.team)
+                    );
+                    continue;
+                }
+            }
+
+            if !flag.is_home() {
+                self.return_timers[flag_index] -= delta_seconds;
+                if self.return_timers[flag_index] <= 0.0 {
+                    state.flags[flag_index].return_home();
+                    shared::bevy::log::info!("{} flag returned", team_name(flag.team));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamemode::tests::live_match;
+    use shared::map::FlagBase;
+
+    const RED_BASE: Vec3 = Vec3::new(-10.0, 0.0, 0.0);
+    const BLUE_BASE: Vec3 = Vec3::new(10.0, 0.0, 0.0);
+    const MIDFIELD: Vec3 = Vec3::ZERO;
+    // a red and a blue player
+    const RED: ClientId = 1;
+    const BLUE: ClientId = 2;
+
+    fn start() -> (CaptureTheFlag, MatchState) {
+        let mut mode = CaptureTheFlag::default();
+        let mut state = live_match(&[Some(0), Some(1)], 2);
+        let map = MapConfig {
+            name: "test".to_string(),
+            geometry: Vec::new(),
+            spawn_points: Vec::new(),
+            pickups: Vec::new(),
+            flag_bases: vec![
+                FlagBase {
+                    team: 0,
+                    position: RED_BASE,
+                },
+                FlagBase {
+                    team: 1,
+                    position: BLUE_BASE,
+                },
+            ],
+        };
+        mode.start(&mut state, &map);
+        (mode, state)
+    }
+
+    #[test]
+    fn kills_only_count_for_the_player() {
+        let (mut mode, mut state) = start();
+        mode.on_kill(&mut state, RED, BLUE);
+        mode.on_kill(&mut state, BLUE, BLUE);
+
+        assert_eq!(state.player(RED).unwrap().score, 1);
+        assert_eq!(state.player(BLUE).unwrap().score, -1);
+        assert_eq!(state.team_scores, vec![0, 0]);
+    }
+
+    #[test]
+    fn bringing_the_enemy_flag_home_captures_it() {
+        let (mut mode, mut state) = start();
+        mode.update(&mut state, &[(RED, BLUE_BASE)], 0.1);
+        assert_eq!(state.flags[1].carrier, Some(RED));
+
+        // carrying it doesn't change the match state every tick
+        mode.update(&mut state, &[(RED, MIDFIELD)], 0.1);
+        assert_eq!(state.flags[1].position, BLUE_BASE);
+        assert_eq!(state.team_scores, vec![0, 0]);
+
+        mode.update(&mut state, &[(RED, RED_BASE)], 0.1);
+        assert!(state.flags[1].is_home());
+        assert_eq!(state.team_scores, vec![1, 0]);
+        assert_eq!(state.player(RED).unwrap().score, CAPTURE_POINTS);
+    }
+
+    #[test]
+    fn no_capture_while_your_own_flag_is_taken() {
+        let (mut mode, mut state) = start();
+        mode.update(&mut state, &[(RED, BLUE_BASE), (BLUE, RED_BASE)], 0.1);
+        mode.update(&mut state, &[(RED, RED_BASE), (BLUE, MIDFIELD)], 0.1);
+
+        assert_eq!(state.flags[1].carrier, Some(RED));
+        assert_eq!(state.team_scores, vec![0, 0]);
+    }
+
+    #[test]
+    fn dropped_flags_are_returned_by_their_team_or_in_time() {
+        let (mut mode, mut state) = start();
+        mode.update(&mut state, &[(RED, BLUE_BASE)], 0.1);
+        mode.update(&mut state, &[(RED, MIDFIELD)], 0.1);
+        // the carrier died
+        mode.update(&mut state, &[], 0.1);
+        assert_eq!(state.flags[1].carrier, None);
+        assert_eq!(state.flags[1].position, MIDFIELD);
+
+        mode.update(&mut state, &[(BLUE, MIDFIELD)], 0.1);
+        assert!(state.flags[1].is_home());
+
+        mode.update(&mut state, &[(RED, BLUE_BASE)], 0.1);
+        mode.update(&mut state, &[(RED, MIDFIELD)], 0.1);
+        mode.update(&mut state, &[], 0.1);
+        mode.update(&mut state, &[], FLAG_RETURN_SECONDS / 2.0);
+        assert!(!state.flags[1].is_home());
+        mode.update(&mut state, &[], FLAG_RETURN_SECONDS / 2.0);
+        assert!(state.flags[1].is_home());
+    }
+}
@@ -0,0 +1,53 @@
+use shared::bevy_quinnet::shared::ClientId;
+use shared::gamemode::{GameModeKind, MatchState};
+
+use super::GameMode;
+
+/// Free-for-all, every kill is a point and suicides cost one.
+pub struct Deathmatch;
+
+impl GameMode for Deathmatch {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::Deathmatch
+    }
+
+    fn default_score_limit(&self) -> u32 {
+        20
+    }
+
+    fn on_kill(&mut self, state: &mut MatchState, killer: ClientId, victim: ClientId) {
+        let Some(killer_score) = state.player_mut(killer) else {
+            return;
+        };
+        if killer == victim {
+            killer_score.score -= 1;
+        } else {
+            killer_score.score += 1;
+            killer_score.kills += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamemode::tests::live_match;
+
+    #[test]
+    fn kills_score_and_suicides_cost_a_point() {
+        let mut state = live_match(&[None, None], 0);
+        Deathmatch.on_kill(&mut state, 1, 2);
+        Deathmatch.on_kill(&mut state, 1, 2);
+        Deathmatch.on_kill(&mut state, 2, 2);
+
+        let killer = state.player(1).unwrap();
+        assert_eq!((killer.score, killer.kills), (2, 2));
+        let victim = state.player(2).unwrap();
+        assert_eq!((victim.score, victim.kills), (-1, 0));
+        assert_eq!(Deathmatch.winner(&state, false), None);
+        assert_eq!(
+            Deathmatch.winner(&state, true),
+            Some(shared::gamemode::MatchWinner::Player(1))
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use shared::bevy_quinnet::shared::ClientId;
+use shared::gamemode::{GameModeKind, MatchState};
+
+use super::GameMode;
+
+/// Two teams, every enemy killed is a point for the team. Killing a teammate or yourself costs
+/// the team one.
+pub struct TeamDeathmatch;
+
+impl GameMode for TeamDeathmatch {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::TeamDeathmatch
+    }
+
+    fn team_count(&self) -> u8 {
+        2
+    }
+
+    fn default_score_limit(&self) -> u32 {
+        50
+    }
+
+    fn on_kill(&mut self, state: &mut MatchState, killer: ClientId, victim: ClientId) {
+        let victim_team = state.team_of(victim);
+        let Some(killer_score) = state.player_mut(killer) else {
+            return;
+        };
+        let killer_team = killer_score.team;
+
+        let points = if killer != victim && killer_team != victim_team {
+            killer_score.kills += 1;
+            1
+        } else {
+            -1
+        };
+        killer_score.score += points;
+        if let Some(team) = killer_team {
+            state.add_team_score(team, points);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamemode::tests::live_match;
+    use shared::gamemode::MatchWinner;
+
+    #[test]
+    fn enemy_kills_score_for_the_team_and_team_kills_cost_it() {
+        let mut state = live_match(&[Some(0), Some(0), Some(1)], 2);
+        TeamDeathmatch.on_kill(&mut state, 1, 3);
+        TeamDeathmatch.on_kill(&mut state, 2, 3);
+        TeamDeathmatch.on_kill(&mut state, 3, 1);
+        TeamDeathmatch.on_kill(&mut state, 3, 3);
+        TeamDeathmatch.on_kill(&mut state, 2, 1);
+
+        assert_eq!(state.team_scores, vec![1, 0]);
+        let team_killer = state.player(2).unwrap();
+        assert_eq!((team_killer.score, team_killer.kills), (0, 1));
+        let suicidal = state.player(3).unwrap();
+        assert_eq!((suicidal.score, suicidal.kills), (0, 1));
+
+        state.score_limit = 1;
+        assert_eq!(
+            TeamDeathmatch.winner(&state, false),
+            Some(MatchWinner::Team(0))
+        );
+    }
+}
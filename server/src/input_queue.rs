@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+
+use shared::protocol::{InputBufferHealth, PlayerInput};
+
+/// A client's inputs waiting to be simulated, one per tick.
+///
+/// Inputs arrive in bursts and gaps, so a few are kept queued up to smooth that out. When the
+/// queue runs dry it waits to fill back up to the target depth before handing out inputs again.
+#[derive(Default)]
+pub struct InputQueue {
+    inputs: VecDeque<PlayerInput>,
+    /// The newest input id we've seen, anything at or below it is a repeat.
+    newest_input_id: Option<u32>,
+    /// The first input id we've seen and the ticks gone by since, input ids are the weapon
+    /// clock so they can't be allowed to run ahead of the server's.
+    first_input_id: Option<u32>,
+    ticks_since_first_input: u32,
+    is_buffering: bool,
+    /// The last input handed out, characters keep going with it while there's no new one.
+    last_input: Option<PlayerInput>,
+    /// Ticks we had no input to simulate for this client.
+    pub starved_ticks: u32,
+    /// Inputs dropped because the client got too far ahead, or its ids ran ahead of the ticks.
+    pub overflowed_inputs: u32,
+}
+
+impl InputQueue {
+    pub fn push(&mut self, input: &PlayerInput, max_depth: usize) {
+        if self
+            .newest_input_id
+            .is_some_and(|newest_input_id| input.id <= newest_input_id)
+        {
+            return;
+        }
+        let first_input_id = *self.first_input_id.get_or_insert(input.id);
+        // a client whose clock runs fast gets ahead by more than it could ever buffer
+        if input.id > first_input_id + self.ticks_since_first_input + max_depth as u32 {
+            self.overflowed_inputs += 1;
+            return;
+        }
+        if self.newest_input_id.is_none() {
+            self.is_buffering = true;
+        }
+        self.newest_input_id = Some(input.id);
+        self.inputs.push_back(input.clone());
+
+        while self.inputs.len() > max_depth {
+            self.inputs.pop_front();
+            self.overflowed_inputs += 1;
+        }
+    }
+
+    /// The input to simulate this tick, if there's one. Has to be called exactly once per tick.
+    pub fn pop(&mut self, target_depth: usize) -> Option<PlayerInput> {
+        if self.first_input_id.is_some() {
+            self.ticks_since_first_input += 1;
+        }
+        if self.is_buffering && self.inputs.len() < target_depth {
+            self.starved_ticks += 1;
+            return None;
+        }
+        self.is_buffering = false;
+
+        let input = self.inputs.pop_front();
+        if input.is_none() && self.newest_input_id.is_some() {
+            self.starved_ticks += 1;
+            self.is_buffering = true;
+        }
+        if input.is_some() {
+            self.last_input.clone_from(&input);
+        }
+        input
+    }
+
+    /// What to move the character with on a tick `pop` had nothing for: the last input, so it
+    /// keeps moving the way it was, but without firing, reloading or switching weapons again.
+    pub fn stand_in(&self) -> Option<PlayerInput> {
+        self.last_input.as_ref().map(|input| PlayerInput {
+            fire: false,
+            reload: false,
+            switch_weapon: None,
+            ..input.clone()
+        })
+    }
+
+    pub fn depth(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn health(&self, target_depth: usize) -> InputBufferHealth {
+        InputBufferHealth {
+            depth: self.depth() as u32,
+            target_depth: target_depth as u32,
+            starved_ticks: self.starved_ticks,
+            overflowed_inputs: self.overflowed_inputs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(id: u32) -> PlayerInput {
+        PlayerInput {
+            id,
+            ..Default::default()
+        }
+    }
+
+    fn popped_id(queue: &mut InputQueue, target_depth: usize) -> Option<u32> {
+        queue.pop(target_depth).map(|input| input.id)
+    }
+
+    #[test]
+    fn repeated_inputs_are_only_queued_once() {
+        let mut queue = InputQueue::default();
+        // every packet repeats the inputs that weren't acked yet
+        for ids in [1..=2, 1..=3, 2..=4] {
+            for id in ids {
+                queue.push(&input(id), 8);
+            }
+        }
+        // and older ones that arrive late are too late
+        queue.push(&input(0), 8);
+
+        assert_eq!(queue.depth(), 4);
+        let popped: Vec<_> = (0..4).filter_map(|_| popped_id(&mut queue, 0)).collect();
+        assert_eq!(popped, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn a_full_queue_drops_its_oldest_inputs() {
+        let mut queue = InputQueue::default();
+        for id in 1..=5 {
+            queue.push(&input(id), 4);
+        }
+
+        assert_eq!(queue.depth(), 4);
+        assert_eq!(queue.overflowed_inputs, 1);
+        assert_eq!(popped_id(&mut queue, 0), Some(2));
+    }
+
+    #[test]
+    fn a_starved_queue_fills_back_up_before_going_on() {
+        let mut queue = InputQueue::default();
+        // nothing is handed out until the target depth is reached
+        queue.push(&input(1), 8);
+        assert_eq!(popped_id(&mut queue, 2), None);
+        queue.push(&input(2), 8);
+        assert_eq!(popped_id(&mut queue, 2), Some(1));
+        assert_eq!(popped_id(&mut queue, 2), Some(2));
+
+        // then it runs dry and waits again
+        assert_eq!(popped_id(&mut queue, 2), None);
+        queue.push(&input(3), 8);
+        assert_eq!(popped_id(&mut queue, 2), None);
+        queue.push(&input(4), 8);
+        assert_eq!(popped_id(&mut queue, 2), Some(3));
+        assert_eq!(popped_id(&mut queue, 2), Some(4));
+
+        let health = queue.health(2);
+        assert_eq!(health.starved_ticks, 3);
+        assert_eq!(health.overflowed_inputs, 0);
+        assert_eq!(health.depth, 0);
+        assert_eq!(health.target_depth, 2);
+    }
+
+    #[test]
+    fn stand_ins_repeat_the_last_input_without_its_actions() {
+        let mut queue = InputQueue::default();
+        assert!(queue.stand_in().is_none());
+
+        queue.push(
+            &PlayerInput {
+                id: 1,
+                move_forward: true,
+                fire: true,
+                reload: true,
+                switch_weapon: Some(1),
+                ..Default::default()
+            },
+            8,
+        );
+        queue.pop(0);
+        let stand_in = queue.stand_in().unwrap();
+        assert_eq!(stand_in.id, 1);
+        assert!(stand_in.move_forward);
+        assert!(!stand_in.fire && !stand_in.reload);
+        assert_eq!(stand_in.switch_weapon, None);
+    }
+
+    #[test]
+    fn input_ids_cannot_run_ahead_of_the_ticks() {
+        let mut queue = InputQueue::default();
+        queue.push(&input(0), 4);
+        queue.pop(0);
+        queue.pop(0);
+
+        // two ticks went by, so anything past 2 plus the depth came too early
+        queue.push(&input(6), 4);
+        queue.push(&input(7), 4);
+        assert_eq!(queue.overflowed_inputs, 1);
+        assert_eq!(queue.pop(0).map(|input| input.id), Some(6));
+        assert_eq!(queue.pop(0).map(|input| input.id), None);
+    }
+}
@@ -0,0 +1,225 @@
+use shared::avian3d::prelude::*;
+use shared::bevy::prelude::*;
+use shared::character::*;
+
+use crate::events::{LagCompensatedHitEvent, LagCompensatedShotEvent};
+use crate::net::Application;
+use crate::TICKRATE;
+
+// never rewind further than 250ms, otherwise players on a bad connection
+// could hit targets that have long since made it into cover
+const MAX_REWIND_TICKS: u32 = TICKRATE / 4;
+
+/// The tick we rewind to for a shot, the tick the shooter saw clamped to the rewind window.
+pub fn rewind_tick(app: &Application, view_tick: Option<u32>) -> u32 {
+    let current_tick = app.current_tick();
+    view_tick
+        .unwrap_or(current_tick)
+        .clamp(current_tick.saturating_sub(MAX_REWIND_TICKS), current_tick)
+}
+
+/// Tests shots against the other characters as they were on the tick the shooter saw them at.
+///
+/// Rewound colliders are built from the snapshot history rather than by moving the live
+/// characters, so their transforms are never touched and there is nothing to restore.
+pub fn lag_compensation_system(
+    app: Res<Application>,
+    spatial_query: SpatialQuery,
+    characters: Query<(
+        &CharacterState,
+        &CharacterConstants,
+        &CharacterCollider,
+        &Transform,
+    )>,
+    mut shot_events: EventReader<LagCompensatedShotEvent>,
+    mut hit_events: EventWriter<LagCompensatedHitEvent>,
+) {
+    for shot in shot_events.read() {
+        let Ok(direction) = Dir3::new(shot.direction) else {
+            continue;
+        };
+
+        let rewound_tick = rewind_tick(&app, shot.view_tick);
+        let rewound_snapshot = app.snapshot_at(rewound_tick);
+
+        // level geometry is static so it doesn't need rewinding
+        let mut closest_distance = spatial_query
+            .cast_ray(
+                shot.origin,
+                direction,
+                shot.max_distance,
+                true,
+                SpatialQueryFilter::default(),
+            )
+            .map(|hit| hit.time_of_impact)
+            .unwrap_or(shot.max_distance);
+        let mut victim_client_id = None;
+
+        for (char_state, char_constants, char_collider, char_xform) in characters.iter() {
+            if char_state.owner_client_id == shot.shooter_client_id {
+                continue;
+            }
+
+            // characters that didn't exist yet on the rewound tick are tested as they are now
+            let rewound_char_snap = rewound_snapshot.and_then(|snapshot| {
+                snapshot
+                    .characters
+                    .iter()
+                    .find(|c| c.owner_client_id == char_state.owner_client_id)
+            });
+            let rewound_position = rewound_char_snap
+                .and_then(|char_snap| char_snap.position)
+                .unwrap_or(char_xform.translation);
+            let rewound_collider = rewound_char_snap
+                .and_then(|char_snap| char_snap.is_crouching)
+                .map(|is_crouching| {
+                    CharacterCollider::new(
+                        char_constants.radius,
+                        char_constants.height_for(is_crouching),
+                    )
+                })
+                .unwrap_or_else(|| char_collider.clone());
+
+            if let Some((distance, _)) = rewound_collider.shape.cast_ray(
+                rewound_position,
+                Quat::IDENTITY,
+                shot.origin,
+                direction.as_vec3(),
+                closest_distance,
+                true,
+            ) {
+                if distance < closest_distance {
+                    closest_distance = distance;
+                    victim_client_id = Some(char_state.owner_client_id);
+                }
+            }
+        }
+
+        hit_events.send(LagCompensatedHitEvent {
+            shooter_client_id: shot.shooter_client_id,
+            victim_client_id,
+            weapon_config_tag: shot.weapon_config_tag.clone(),
+            fired_input_id: shot.fired_input_id,
+            rewound_tick,
+            origin: shot.origin,
+            endpoint: shot.origin + direction.as_vec3() * closest_distance,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::bevy::ecs::system::RunSystemOnce;
+    use shared::protocol::{CharacterSnapshot, TickSnapshot};
+
+    const CURRENT_TICK: u32 = 100;
+    const VICTIM_CLIENT_ID: u64 = 2;
+    // the shot goes down +X at this height, the victim stands on that line or beside it
+    const SHOT_ORIGIN: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+    const IN_LINE: Vec3 = Vec3::new(5.0, 1.0, 0.0);
+    const OUT_OF_LINE: Vec3 = Vec3::new(5.0, 1.0, 5.0);
+
+    fn snapshot(tick: u32, victim_position: Option<Vec3>) -> TickSnapshot {
+        TickSnapshot {
+            tick,
+            characters: victim_position
+                .map(|position| CharacterSnapshot {
+                    owner_client_id: VICTIM_CLIENT_ID,
+                    position: Some(position),
+                    yaw: Some(0.0),
+                    velocity: Some(Vec3::ZERO),
+                    is_crouching: Some(false),
+                    health: Some(100),
+                    armor: Some(0),
+                    active_weapon: Some(0),
+                    weapons: Some(Vec::new()),
+                })
+                .into_iter()
+                .collect(),
+            ..default()
+        }
+    }
+
+    /// Fires a shot seen at `view_tick` at a victim standing at `live_position`, returns who got
+    /// hit.
+    fn shoot(
+        snapshot_history: Vec<TickSnapshot>,
+        live_position: Vec3,
+        view_tick: Option<u32>,
+    ) -> Option<u64> {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            PhysicsPlugins::default(),
+        ))
+        // avian's collider constructors want these even though we never use them
+        .init_asset::<Mesh>()
+        .init_resource::<shared::bevy::scene::SceneSpawner>()
+        .add_event::<LagCompensatedShotEvent>()
+        .add_event::<LagCompensatedHitEvent>()
+        .insert_resource(Application::with_snapshots(CURRENT_TICK, snapshot_history));
+        app.world_mut().spawn((
+            CharacterState {
+                owner_client_id: VICTIM_CLIENT_ID,
+                velocity: Vec3::ZERO,
+                visuals_offset: Vec3::ZERO,
+                is_grounded: true,
+                is_crouching: false,
+            },
+            CharacterConstants::default(),
+            CharacterCollider::new(CHARACTER_RADIUS, CHARACTER_HEIGHT),
+            Transform::from_translation(live_position),
+        ));
+        app.update();
+
+        app.world_mut().send_event(LagCompensatedShotEvent {
+            shooter_client_id: 1,
+            weapon_config_tag: "rifle".to_string(),
+            fired_input_id: 0,
+            view_tick,
+            origin: SHOT_ORIGIN,
+            direction: Vec3::X,
+            max_distance: 100.0,
+        });
+        app.world_mut().run_system_once(lag_compensation_system);
+
+        let hit_events = app.world().resource::<Events<LagCompensatedHitEvent>>();
+        let hit = hit_events.iter_current_update_events().next().unwrap();
+        hit.victim_client_id
+    }
+
+    #[test]
+    fn rewinds_no_further_than_the_rewind_window() {
+        let app = Application::with_snapshots(CURRENT_TICK, Vec::new());
+        assert_eq!(rewind_tick(&app, Some(95)), 95);
+        assert_eq!(rewind_tick(&app, Some(10)), CURRENT_TICK - MAX_REWIND_TICKS);
+        assert_eq!(rewind_tick(&app, Some(CURRENT_TICK + 10)), CURRENT_TICK);
+        assert_eq!(rewind_tick(&app, None), CURRENT_TICK);
+    }
+
+    #[test]
+    fn hits_where_the_victim_was_on_the_rewound_tick() {
+        let history = vec![snapshot(95, Some(IN_LINE))];
+        assert_eq!(
+            shoot(history, OUT_OF_LINE, Some(95)),
+            Some(VICTIM_CLIENT_ID)
+        );
+    }
+
+    #[test]
+    fn misses_where_the_victim_was_no_longer_on_the_rewound_tick() {
+        let history = vec![snapshot(95, Some(OUT_OF_LINE))];
+        assert_eq!(shoot(history, IN_LINE, Some(95)), None);
+    }
+
+    #[test]
+    fn falls_back_to_live_positions_without_a_rewound_position() {
+        // no snapshot for the tick at all
+        assert_eq!(shoot(Vec::new(), IN_LINE, Some(95)), Some(VICTIM_CLIENT_ID));
+        // a snapshot from before the victim existed
+        let history = vec![snapshot(95, None)];
+        assert_eq!(shoot(history, IN_LINE, Some(95)), Some(VICTIM_CLIENT_ID));
+    }
+}
@@ -4,30 +4,41 @@ use shared::bevy::asset::LoadedFolder;
 use shared::bevy::log::LogPlugin;
 use shared::bevy::prelude::*;
 use shared::bevy_common_assets::ron::RonAssetPlugin;
-use shared::bevy_quinnet::server::QuinnetServerPlugin;
-use shared::resources::DataAssetHandles;
+use shared::bevy_quinnet::server::{server_listening, QuinnetServerPlugin};
+use shared::character::CharacterConstants;
+use shared::map::{map_spawned, shared_asset_source, MapConfig, SHARED_ASSET_SOURCE};
+use shared::resources::{DataAssetHandles, MovementProfile};
 use shared::weapons::WeaponConfig;
+use shared::TICKRATE;
 use std::time::Duration;
 
 mod characters;
+mod chat;
+mod damage;
 mod events;
 mod gamemode;
+mod input_queue;
+mod lag_compensation;
 mod net;
-
-const TICKRATE: u32 = 64;
+mod pickups;
+mod projectiles;
+mod spawning;
 
 #[derive(Resource, Default, DerefMut, Deref)]
 pub struct DataFolder(Handle<LoadedFolder>);
 
 fn main() {
     App::new()
+        .register_asset_source(SHARED_ASSET_SOURCE, shared_asset_source())
         .add_plugins((
             MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
                 1.0 / 200.0,
             ))),
             AssetPlugin::default(),
-            HierarchyPlugin::default(), // needed by Avian
+            HierarchyPlugin, // needed by Avian
             RonAssetPlugin::<WeaponConfig>::new(&["weapon.ron"]),
+            RonAssetPlugin::<CharacterConstants>::new(&["character.ron"]),
+            RonAssetPlugin::<MapConfig>::new(&["map.ron"]),
             LogPlugin::default(),
             QuinnetServerPlugin::default(),
             PhysicsPlugins::default(),
@@ -35,7 +46,30 @@ fn main() {
         //====================================================
         // systems at startup
         //====================================================
-        .add_systems(Startup, (setup, net::start_listening_system))
+        .add_systems(
+            Startup,
+            (
+                setup,
+                net::configure_server_system,
+                gamemode::configure_match_system,
+                gamemode::load_map_system,
+            )
+                .chain(),
+        )
+        //====================================================
+        // systems updating every frame
+        //====================================================
+        .add_systems(
+            Update,
+            (
+                net::data_load_system,
+                net::movement_profile_load_system,
+                gamemode::spawn_map_system,
+                // clients aren't let in before there is a level to put them in
+                net::start_listening_system.run_if(map_spawned.and_then(not(server_listening))),
+            )
+                .chain(),
+        )
         //====================================================
         // systems updating at the fixed tickrate
         //====================================================
@@ -43,115 +77,105 @@ fn main() {
         .add_systems(
             FixedUpdate,
             (
-                net::handle_received_messages_system,
-                net::handle_server_events_system,
-                net::handle_client_connected_system,
-                net::handle_client_disconnected_system,
-                net::handle_client_input_system,
-                gamemode::handle_client_connected_system,
+                (
+                    net::handle_received_messages_system,
+                    net::handle_server_events_system,
+                    net::handle_connect_requests_system,
+                    net::disconnect_rejected_clients_system,
+                    net::handle_client_connected_system,
+                    net::handle_client_disconnected_system,
+                    net::handle_client_input_system,
+                )
+                    .chain(),
+                (
+                    gamemode::handle_client_connected_system,
+                    gamemode::match_phase_system,
+                    spawning::respawn_timer_system,
+                    spawning::spawn_system,
+                )
+                    .chain(),
                 characters::consume_input_system,
-                characters::despawn_system,
-                net::snapshot_system,
-                net::data_load_system,
+                pickups::pickup_system,
+                lag_compensation::lag_compensation_system,
+                net::broadcast_hitscan_system,
+                projectiles::spawn_projectile_system,
+                projectiles::simulate_projectiles_system,
+                damage::hit_damage_system,
+                damage::projectile_impact_system,
+                damage::apply_damage_system,
+                damage::death_system,
+                (
+                    gamemode::score_system,
+                    spawning::queue_respawn_system,
+                    spawning::spawn_protection_system,
+                    characters::despawn_system,
+                )
+                    .chain(),
+                (
+                    chat::announce_joins_system,
+                    chat::announcement_system,
+                    chat::chat_system,
+                    net::broadcast_kills_system,
+                    net::update_pings_system,
+                    net::snapshot_system,
+                    net::send_match_state_system,
+                )
+                    .chain(),
+                shared::character::update_character_colliders_system,
             )
-                .chain(),
+                .chain()
+                .run_if(server_listening),
         )
         //====================================================
         // resources
         //====================================================
         .insert_resource(net::Application::default())
+        .init_resource::<net::ServerConfig>()
         .insert_resource(SceneSpawner::default())
         .insert_resource(Assets::<Mesh>::default()) // needed by Avian
         .insert_resource(DataFolder::default())
         .init_resource::<DataAssetHandles>()
+        .init_resource::<MovementProfile>()
+        .init_resource::<gamemode::MatchSettings>()
+        .init_resource::<spawning::SpawnState>()
+        .init_resource::<chat::ChatAllowances>()
         //====================================================
         // events
         //====================================================
+        .add_event::<events::ClientConnectRequestEvent>()
         .add_event::<events::ClientConnectedEvent>()
         .add_event::<events::ClientDisconnectedEvent>()
         .add_event::<events::ClientInputEvent>()
+        .add_event::<events::ClientChatEvent>()
+        .add_event::<events::AnnouncementEvent>()
+        .add_event::<events::LagCompensatedShotEvent>()
+        .add_event::<events::LagCompensatedHitEvent>()
+        .add_event::<events::DamageEvent>()
+        .add_event::<events::CharacterDeathEvent>()
+        .add_event::<events::ProjectileImpactEvent>()
+        .add_event::<shared::weapons::WeaponFiredProjectileEvent>()
+        .add_event::<shared::weapons::WeaponDryFiredEvent>()
         .add_event::<shared::character::CharacterDespawnEvent>()
+        .add_event::<shared::character::CharacterSpawnEvent>()
         //====================================================
         .run();
 }
 
-fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut data_asset_handles: ResMut<DataAssetHandles>,
-) {
+fn setup(asset_server: Res<AssetServer>, mut data_asset_handles: ResMut<DataAssetHandles>) {
     data_asset_handles.weapon_configs.insert(
         "rocket_launcher".into(),
         asset_server.load::<WeaponConfig>("data/rocket_launcher.weapon.ron"),
     );
-
-    // floor
-    commands.spawn((
-        SpatialBundle {
-            transform: Transform::from_translation(Vec3::new(0.0, -0.5, 0.0)),
-            ..default()
-        },
-        Collider::cuboid(10.0, 1.0, 10.0),
-        RigidBody::Static,
-    ));
-
-    // wall 1.
-    commands.spawn((
-        SpatialBundle {
-            transform: Transform::from_translation(Vec3::new(-5.0, 2.0, 0.0)),
-            ..default()
-        },
-        Collider::cuboid(1.0, 4.0, 10.0),
-        RigidBody::Static,
-    ));
-
-    // wall 2.
-    commands.spawn((
-        SpatialBundle {
-            transform: Transform::from_translation(Vec3::new(5.0, 2.0, 0.0)),
-            ..default()
-        },
-        Collider::cuboid(1.0, 4.0, 10.0),
-        RigidBody::Static,
-    ));
-
-    // wall 3.
-    commands.spawn((
-        SpatialBundle {
-            transform: Transform::from_translation(Vec3::new(0.0, 2.0, -5.0)),
-            ..default()
-        },
-        Collider::cuboid(10.0, 4.0, 1.0),
-        RigidBody::Static,
-    ));
-
-    // wall 4.
-    commands.spawn((
-        SpatialBundle {
-            transform: Transform::from_translation(Vec3::new(0.0, 2.0, 5.0)),
-            ..default()
-        },
-        Collider::cuboid(10.0, 4.0, 1.0),
-        RigidBody::Static,
-    ));
-
-    // pillar 1.
-    commands.spawn((
-        SpatialBundle {
-            transform: Transform::from_translation(Vec3::new(-1.0, 2.0, -1.0)),
-            ..default()
-        },
-        Collider::cylinder(0.5, 8.0),
-        RigidBody::Static,
-    ));
-
-    // small step
-    commands.spawn((
-        SpatialBundle {
-            transform: Transform::from_translation(Vec3::new(1.0, 0.05, 1.0)),
-            ..default()
-        },
-        Collider::cuboid(2.0, 0.2, 2.0),
-        RigidBody::Static,
-    ));
+    data_asset_handles.weapon_configs.insert(
+        "rifle".into(),
+        asset_server.load::<WeaponConfig>("data/rifle.weapon.ron"),
+    );
+    data_asset_handles.character_constants.insert(
+        "default".into(),
+        asset_server.load::<CharacterConstants>("data/default.character.ron"),
+    );
+    data_asset_handles.character_constants.insert(
+        "quake".into(),
+        asset_server.load::<CharacterConstants>("data/quake.character.ron"),
+    );
 }
@@ -1,4 +1,3 @@
-use shared::bevy::asset::LoadedFolder;
 use shared::bevy::prelude::*;
 use shared::bevy_quinnet::server::certificate::CertificateRetrievalMode;
 use shared::bevy_quinnet::server::ConnectionLostEvent;
@@ -6,54 +5,314 @@ use shared::bevy_quinnet::server::QuinnetServer;
 use shared::bevy_quinnet::server::ServerEndpointConfiguration;
 use shared::bevy_quinnet::shared::ClientId;
 use shared::character::*;
+use shared::gamemode::MatchState;
+use shared::map::CurrentMap;
+use shared::pickups::WeaponPickup;
+use shared::projectiles::Projectile;
 use shared::protocol::*;
-use shared::resources::DataAssetHandles;
-use shared::weapons::WeaponConfig;
-use std::any::TypeId;
+use shared::resources::MovementProfile;
+use shared::weapons::{WeaponConfig, WeaponFiredHitscanEvent, WeaponInventory};
 
+use crate::events::AnnouncementEvent;
+use crate::events::CharacterDeathEvent;
+use crate::events::ClientChatEvent;
+use crate::events::ClientConnectRequestEvent;
 use crate::events::ClientConnectedEvent;
 use crate::events::ClientDisconnectedEvent;
 use crate::events::ClientInputEvent;
-use crate::DataFolder;
+use crate::events::LagCompensatedHitEvent;
+use crate::gamemode::MatchSettings;
+use crate::input_queue::InputQueue;
 use crate::TICKRATE;
 
-#[derive(Resource)]
+const PING_UPDATE_SECONDS: f32 = 2.0;
+// rejected clients are disconnected after this many ticks, so the reason reaches them first
+const REJECT_DISCONNECT_DELAY_TICKS: u32 = TICKRATE / 2;
+// a second of inputs, older ones are dropped if a client gets further ahead than that
+const MAX_INPUT_BUFFER_DEPTH: usize = TICKRATE as usize;
+
+#[derive(Resource, Default)]
 pub struct Application {
     next_tick: u32,
     snapshot_history: Vec<TickSnapshot>,
     pub clients: Vec<ClientInfo>,
+    /// Rejected clients and the tick to disconnect them on.
+    rejected_clients: Vec<(ClientId, u32)>,
+}
+
+#[derive(Resource)]
+pub struct ServerConfig {
+    pub server_name: String,
+    pub max_players: usize,
+    /// Compared ignoring case.
+    pub banned_usernames: Vec<String>,
+    /// How many inputs we try to keep queued for each client, more absorbs more jitter but adds
+    /// that many ticks of latency.
+    pub input_buffer_depth: usize,
 }
-impl Default for Application {
+
+impl Default for ServerConfig {
     fn default() -> Self {
+        ServerConfig {
+            server_name: "Unnamed Server".to_string(),
+            max_players: 16,
+            banned_usernames: Vec::new(),
+            input_buffer_depth: 2,
+        }
+    }
+}
+
+/// `SERVER_NAME`, `MAX_PLAYERS`, `BANNED_USERNAMES` (comma separated) and `INPUT_BUFFER_DEPTH`
+/// (in ticks) override the defaults.
+pub fn configure_server_system(mut server_config: ResMut<ServerConfig>) {
+    if let Ok(server_name) = std::env::var("SERVER_NAME") {
+        server_config.server_name = server_name;
+    }
+    if let Some(max_players) = std::env::var("MAX_PLAYERS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        server_config.max_players = max_players;
+    }
+    if let Ok(banned_usernames) = std::env::var("BANNED_USERNAMES") {
+        server_config.banned_usernames = banned_usernames
+            .split(',')
+            .map(|username| username.trim().to_string())
+            .filter(|username| !username.is_empty())
+            .collect();
+    }
+    if let Some(input_buffer_depth) = std::env::var("INPUT_BUFFER_DEPTH")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+    {
+        server_config.input_buffer_depth = input_buffer_depth.clamp(1, MAX_INPUT_BUFFER_DEPTH);
+    }
+}
+
+impl Application {
+    /// The tick currently being simulated, it becomes a snapshot at the end of the frame.
+    pub fn current_tick(&self) -> u32 {
+        self.next_tick
+    }
+
+    pub fn snapshot_at(&self, tick: u32) -> Option<&TickSnapshot> {
+        self.snapshot_history
+            .iter()
+            .find(|snapshot| snapshot.tick == tick)
+    }
+
+    /// Clients that finished joining, broadcasts go to these and not to every connection.
+    pub fn client_ids(&self) -> Vec<ClientId> {
+        self.clients.iter().map(|c| c.client_id).collect()
+    }
+}
+
+#[cfg(test)]
+impl Application {
+    /// An application about to simulate `next_tick`, with `snapshot_history` to rewind into.
+    pub fn with_snapshots(next_tick: u32, snapshot_history: Vec<TickSnapshot>) -> Self {
         Application {
-            next_tick: 0,
-            snapshot_history: Vec::new(),
-            clients: Vec::new(),
+            next_tick,
+            snapshot_history,
+            ..default()
         }
     }
 }
 
 pub struct ClientInfo {
     pub client_id: ClientId,
-    pub input_to_process: Option<PlayerInput>,
+    pub username: String,
+    pub input_queue: InputQueue,
     pub client_last_acked_tick: Option<u32>,
     pub server_last_processed_input_id: Option<u32>,
+    /// `None` when the game mode doesn't have teams.
+    pub team: Option<u8>,
 }
 
+/// `username`, or with a number after it when someone already goes by it.
+fn unique_username(username: &str, is_taken: impl Fn(&str) -> bool) -> String {
+    if !is_taken(username) {
+        return username.to_string();
+    }
+    (2..)
+        .map(|number| {
+            let suffix = format!(" ({})", number);
+            // the number has to fit, so long names give up their end
+            let name: String = username
+                .chars()
+                .take(MAX_USERNAME_LENGTH - suffix.len())
+                .collect();
+            format!("{}{}", name.trim_end(), suffix)
+        })
+        .find(|username| !is_taken(username))
+        .unwrap()
+}
+
+/// Lets clients in if they run our protocol version and there's room for them, or tells them
+/// why not.
+pub fn handle_connect_requests_system(
+    server_config: Res<ServerConfig>,
+    mut server: ResMut<QuinnetServer>,
+    mut app: ResMut<Application>,
+    mut request_events: EventReader<ClientConnectRequestEvent>,
+    mut connected_events: EventWriter<ClientConnectedEvent>,
+) {
+    let tick = app.current_tick();
+    for event in request_events.read() {
+        // a second request from a client that's already in
+        if app.clients.iter().any(|c| c.client_id == event.client_id) {
+            continue;
+        }
+
+        let result = if event.protocol_version != PROTOCOL_VERSION {
+            Err(RejectReason::VersionMismatch {
+                server_version: PROTOCOL_VERSION,
+                client_version: event.protocol_version,
+            })
+        } else if app.clients.len() >= server_config.max_players {
+            Err(RejectReason::ServerFull {
+                max_players: server_config.max_players,
+            })
+        } else {
+            validate_username(&event.username)
+                .map_err(RejectReason::BadUsername)
+                .and_then(|username| {
+                    if server_config
+                        .banned_usernames
+                        .iter()
+                        .any(|banned| banned.eq_ignore_ascii_case(&username))
+                    {
+                        Err(RejectReason::Banned)
+                    } else {
+                        Ok(unique_username(&username, |taken| {
+                            app.clients
+                                .iter()
+                                .any(|c| c.username.eq_ignore_ascii_case(taken))
+                        }))
+                    }
+                })
+        };
+
+        match result {
+            Ok(username) => {
+                connected_events.send(ClientConnectedEvent {
+                    client_id: event.client_id,
+                    username,
+                });
+            }
+            Err(reason) => {
+                shared::bevy::log::info!("Rejected client {}: {}", event.client_id, reason);
+                if let Err(err) = server.endpoint_mut().send_message_on(
+                    event.client_id,
+                    ServerChannels::ImportantData,
+                    ServerMessage::Rejected(reason),
+                ) {
+                    shared::bevy::log::error!("Failed to send rejection: {}", err);
+                }
+                app.rejected_clients
+                    .push((event.client_id, tick + REJECT_DISCONNECT_DELAY_TICKS));
+            }
+        }
+    }
+}
+
+pub fn disconnect_rejected_clients_system(
+    mut server: ResMut<QuinnetServer>,
+    mut app: ResMut<Application>,
+) {
+    let tick = app.current_tick();
+    app.rejected_clients.retain(|(client_id, disconnect_tick)| {
+        if tick < *disconnect_tick {
+            return true;
+        }
+        // they may have left on their own already
+        let _ = server.endpoint_mut().disconnect_client(*client_id);
+        false
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn handle_client_connected_system(
     weapon_configs_assets: Res<Assets<WeaponConfig>>,
+    movement_profile: Res<MovementProfile>,
+    current_map: Res<CurrentMap>,
+    server_config: Res<ServerConfig>,
+    match_settings: Res<MatchSettings>,
     mut server: ResMut<QuinnetServer>,
     mut app: ResMut<Application>,
     mut events: EventReader<ClientConnectedEvent>,
 ) {
+    let weapon_configs: Vec<&WeaponConfig> = weapon_configs_assets
+        .iter()
+        .map(|(_, weapon_config)| weapon_config)
+        .collect();
+
     for event in events.read() {
         app.clients.push(ClientInfo {
             client_id: event.client_id,
-            input_to_process: None,
+            username: event.username.clone(),
+            input_queue: InputQueue::default(),
             client_last_acked_tick: None,
             server_last_processed_input_id: None,
+            team: None,
         });
 
+        if let Err(err) = server.endpoint_mut().send_message_on(
+            event.client_id,
+            ServerChannels::ImportantData,
+            ServerMessage::Welcome {
+                client_id: event.client_id,
+                tickrate: TICKRATE,
+                content_hash: content_hash(
+                    current_map.collision_hash.unwrap_or_default(),
+                    &weapon_configs,
+                ),
+                settings: ServerSettings {
+                    server_name: server_config.server_name.clone(),
+                    max_players: server_config.max_players,
+                    game_mode: match_settings.game_mode,
+                    map: current_map.name.clone(),
+                    weapon_config_tags: weapon_configs
+                        .iter()
+                        .map(|weapon_config| weapon_config.tag.clone())
+                        .collect(),
+                },
+            },
+        ) {
+            shared::bevy::log::error!(
+                "Failed to send welcome to client ({}): {}",
+                event.client_id,
+                err
+            );
+        }
+
+        if let Err(err) = server.endpoint_mut().send_message_on(
+            event.client_id,
+            ServerChannels::ImportantData,
+            ServerMessage::LoadMap {
+                name: current_map.name.clone(),
+                collision_hash: current_map.collision_hash.unwrap_or_default(),
+            },
+        ) {
+            shared::bevy::log::error!(
+                "Failed to send map to client ({}): {}",
+                event.client_id,
+                err
+            );
+        }
+
+        if let Err(err) = server.endpoint_mut().send_message_on(
+            event.client_id,
+            ServerChannels::ImportantData,
+            ServerMessage::MovementProfile(movement_profile.0.clone()),
+        ) {
+            shared::bevy::log::error!(
+                "Failed to send movement profile to client ({}): {}",
+                event.client_id,
+                err
+            );
+        }
+
         for weapon_config in weapon_configs_assets.iter() {
             if let Err(err) = server.endpoint_mut().send_message_on(
                 event.client_id,
@@ -82,18 +341,40 @@ pub fn handle_client_input_system(
             .iter_mut()
             .find(|c| c.client_id == event.client_id)
         {
-            client_info.input_to_process = Some(event.input.clone());
-            client_info.client_last_acked_tick = event.input.server_tick;
+            for input in event.inputs.iter().rev().take(MAX_REDUNDANT_INPUTS).rev() {
+                client_info.input_queue.push(input, MAX_INPUT_BUFFER_DEPTH);
+            }
+            // a packet that got overtaken doesn't take the ack back to a baseline the client
+            // may already have dropped
+            if let Some(newest_input) = event.inputs.last() {
+                client_info.client_last_acked_tick = client_info
+                    .client_last_acked_tick
+                    .max(newest_input.server_tick);
+            }
         }
     }
 }
 
+fn announce_leave(
+    app: &Application,
+    client_id: ClientId,
+    announcement_events: &mut EventWriter<AnnouncementEvent>,
+) {
+    if let Some(client) = app.clients.iter().find(|c| c.client_id == client_id) {
+        announcement_events.send(AnnouncementEvent {
+            text: format!("{} left", client.username),
+        });
+    }
+}
+
 pub fn handle_client_disconnected_system(
     mut server: ResMut<QuinnetServer>,
     mut app: ResMut<Application>,
     mut events: EventReader<ClientDisconnectedEvent>,
+    mut announcement_events: EventWriter<AnnouncementEvent>,
 ) {
     for event in events.read() {
+        announce_leave(&app, event.client_id, &mut announcement_events);
         app.clients.retain(|c| c.client_id != event.client_id);
         if let Err(err) = server.endpoint_mut().disconnect_client(event.client_id) {
             shared::bevy::log::error!("Failed to disconnect client: {:?}", err);
@@ -104,30 +385,54 @@ pub fn handle_client_disconnected_system(
 }
 
 pub fn handle_received_messages_system(
+    app: Res<Application>,
     mut server: ResMut<QuinnetServer>,
     mut input_events: EventWriter<ClientInputEvent>,
-    mut connected_events: EventWriter<ClientConnectedEvent>,
+    mut connect_request_events: EventWriter<ClientConnectRequestEvent>,
     mut disconnected_events: EventWriter<ClientDisconnectedEvent>,
+    mut chat_events: EventWriter<ClientChatEvent>,
 ) {
     let endpoint = server.endpoint_mut();
     for client_id in endpoint.clients() {
         while let Some((_, message)) = endpoint.try_receive_message_from::<ClientMessage>(client_id)
         {
             match message {
-                ClientMessage::PlayerInput(input) => {
-                    input_events.send(ClientInputEvent { client_id, input });
+                ClientMessage::PlayerInputs(inputs) => {
+                    input_events.send(ClientInputEvent { client_id, inputs });
                 }
-                ClientMessage::Connect { username } => {
-                    connected_events.send(ClientConnectedEvent {
+                ClientMessage::Connect {
+                    username,
+                    protocol_version,
+                } => {
+                    connect_request_events.send(ClientConnectRequestEvent {
                         client_id,
                         username,
+                        protocol_version,
                     });
                 }
+                // answered here and now, any delay would count towards the round trip
+                ClientMessage::Ping { client_seconds } => {
+                    if let Err(err) = endpoint.send_message_on(
+                        client_id,
+                        ServerChannels::Pings,
+                        ServerMessage::Pong {
+                            client_seconds,
+                            server_tick: app.current_tick(),
+                        },
+                    ) {
+                        shared::bevy::log::error!("Failed to send pong: {}", err);
+                    }
+                }
                 ClientMessage::Disconnect {} => {
                     disconnected_events.send(ClientDisconnectedEvent { client_id });
                 }
-
-                _ => {}
+                ClientMessage::ChatMessage { text, team_only } => {
+                    chat_events.send(ClientChatEvent {
+                        client_id,
+                        text,
+                        team_only,
+                    });
+                }
             }
         }
     }
@@ -136,9 +441,11 @@ pub fn handle_received_messages_system(
 pub fn handle_server_events_system(
     mut event_writer: EventWriter<CharacterDespawnEvent>,
     mut connection_lost_events: EventReader<ConnectionLostEvent>,
+    mut announcement_events: EventWriter<AnnouncementEvent>,
     mut app: ResMut<Application>,
 ) {
     for client in connection_lost_events.read() {
+        announce_leave(&app, client.id, &mut announcement_events);
         app.clients.retain(|c| c.client_id != client.id);
         event_writer.send(CharacterDespawnEvent {
             client_id: client.id,
@@ -160,22 +467,61 @@ pub fn start_listening_system(mut server: ResMut<QuinnetServer>) {
 }
 
 pub fn snapshot_system(
+    server_config: Res<ServerConfig>,
     mut server: ResMut<QuinnetServer>,
     mut app: ResMut<Application>,
-    mut characters: Query<(&CharacterState, &Transform)>,
+    mut characters: Query<(
+        &CharacterState,
+        &Transform,
+        &CharacterHealth,
+        &WeaponInventory,
+    )>,
+    projectiles: Query<(Entity, &Projectile, &Transform)>,
+    pickups: Query<(&WeaponPickup, &Transform)>,
 ) {
     let mut snapshot = TickSnapshot {
         tick: app.next_tick,
-        acked_input_id: None, // gets filled in before sending to client
+        baseline_tick: None,
+        // these two get filled in before sending to each client
+        acked_input_id: None,
+        input_buffer: None,
         characters: Vec::new(),
+        projectiles: Vec::new(),
+        pickups: Vec::new(),
     };
 
     // capture the state of all characters
-    for (char_state, char_xform) in characters.iter_mut() {
+    for (char_state, char_xform, char_health, inventory) in characters.iter_mut() {
         snapshot.characters.push(CharacterSnapshot {
             owner_client_id: char_state.owner_client_id,
             position: Some(char_xform.translation),
+            yaw: Some(char_xform.rotation.to_euler(EulerRot::YXZ).0),
             velocity: Some(char_state.velocity),
+            is_crouching: Some(char_state.is_crouching),
+            health: Some(char_health.health),
+            armor: Some(char_health.armor),
+            active_weapon: Some(inventory.active_slot as u8),
+            weapons: Some(inventory.to_snapshot()),
+        });
+    }
+
+    for (entity, projectile, projectile_xform) in projectiles.iter() {
+        snapshot.projectiles.push(ProjectileSnapshot {
+            id: entity.to_bits(),
+            owner_client_id: projectile.owner_client_id,
+            weapon_config_tag: projectile.weapon_config_tag.clone(),
+            fired_input_id: projectile.fired_input_id,
+            position: projectile_xform.translation,
+            velocity: projectile.velocity,
+        });
+    }
+
+    for (pickup, pickup_xform) in pickups.iter() {
+        snapshot.pickups.push(PickupSnapshot {
+            id: pickup.id,
+            weapon_config_tag: pickup.weapon_config_tag.clone(),
+            position: pickup_xform.translation,
+            available: pickup.is_available(),
         });
     }
 
@@ -191,30 +537,31 @@ pub fn snapshot_system(
     for client_info in &app.clients {
         // this tells the client we acked their input
         snapshot.acked_input_id = client_info.server_last_processed_input_id;
+        snapshot.input_buffer = Some(
+            client_info
+                .input_queue
+                .health(server_config.input_buffer_depth),
+        );
 
-        if let Some(last_acked_tick) = client_info.client_last_acked_tick {
-            if let Some(last_acked_snapshot) = app
-                .snapshot_history
-                .iter()
-                .find(|snapshot| snapshot.tick == last_acked_tick)
-            {
-                if let Err(err) = endpoint.send_message_on(
-                    client_info.client_id,
-                    ServerChannels::SnapshotDiff,
-                    ServerMessage::TickSnapshot(snapshot.diff(last_acked_snapshot)),
-                ) {
-                    shared::bevy::log::error!("Failed to send snapshot to client: {:?}", err);
-                    return;
-                }
-            }
-        }
-        // if we don't have a snapshot to diff against, send the full snapshot
+        // the last snapshot they acked is one they're sure to still have, without one they get
+        // everything
+        let baseline = client_info
+            .client_last_acked_tick
+            .and_then(|last_acked_tick| app.snapshot_at(last_acked_tick));
+        let client_snapshot = match baseline {
+            Some(baseline) => snapshot.diff(baseline),
+            None => snapshot.clone(),
+        };
         if let Err(err) = endpoint.send_message_on(
             client_info.client_id,
             ServerChannels::SnapshotDiff,
-            ServerMessage::TickSnapshot(snapshot.clone()),
+            ServerMessage::TickSnapshot(client_snapshot),
         ) {
-            shared::bevy::log::error!("Failed to send snapshot to client: {:?}", err);
+            shared::bevy::log::error!(
+                "Failed to send snapshot to client ({}): {:?}",
+                client_info.client_id,
+                err
+            );
         }
     }
 
@@ -222,18 +569,45 @@ pub fn snapshot_system(
     app.next_tick += 1;
 }
 
+pub fn broadcast_hitscan_system(
+    app: Res<Application>,
+    mut server: ResMut<QuinnetServer>,
+    mut hit_events: EventReader<LagCompensatedHitEvent>,
+) {
+    let clients = app.client_ids();
+    for hit in hit_events.read() {
+        if let Err(err) = server.endpoint_mut().send_group_message_on(
+            clients.iter(),
+            ServerChannels::GameEvents,
+            ServerMessage::WeaponFiredHitscan(WeaponFiredHitscanEvent {
+                owner_client_id: hit.shooter_client_id,
+                weapon_config_tag: hit.weapon_config_tag.clone(),
+                fired_input_id: hit.fired_input_id,
+                origin: hit.origin,
+                endpoint: hit.endpoint,
+            }),
+        ) {
+            shared::bevy::log::error!("Failed to send hitscan event: {}", err);
+        }
+    }
+}
+
 pub fn data_load_system(
     weapon_config_assets: Res<Assets<WeaponConfig>>,
     mut events: EventReader<AssetEvent<WeaponConfig>>,
+    app: Res<Application>,
     mut server: ResMut<QuinnetServer>,
 ) {
     for event in events.read() {
         if let AssetEvent::Added { id: asset_id } | AssetEvent::Modified { id: asset_id } = event {
             if let Some(weapon_config) = weapon_config_assets.get(*asset_id) {
                 shared::bevy::log::info!("Loaded weapon config: {:?}", weapon_config);
-                let clients = server.endpoint_mut().clients();
-                if let Err(err) = server.endpoint_mut().send_group_message_on(
-                    clients.iter(),
+                // clients that connect later are sent every config when they do
+                let Some(endpoint) = server.get_endpoint_mut() else {
+                    continue;
+                };
+                if let Err(err) = endpoint.send_group_message_on(
+                    app.client_ids().iter(),
                     ServerChannels::ImportantData,
                     ServerMessage::WeaponConfig(weapon_config.clone()),
                 ) {
@@ -243,3 +617,132 @@ pub fn data_load_system(
         }
     }
 }
+
+/// Switches to the match's movement profile once it's loaded, and again whenever it's edited.
+pub fn movement_profile_load_system(
+    character_constants_assets: Res<Assets<CharacterConstants>>,
+    match_settings: Res<MatchSettings>,
+    mut movement_profile: ResMut<MovementProfile>,
+    mut events: EventReader<AssetEvent<CharacterConstants>>,
+    app: Res<Application>,
+    mut server: ResMut<QuinnetServer>,
+    mut characters: Query<&mut CharacterConstants>,
+) {
+    for event in events.read() {
+        if let AssetEvent::Added { id: asset_id } | AssetEvent::Modified { id: asset_id } = event {
+            let Some(constants) = character_constants_assets
+                .get(*asset_id)
+                .filter(|constants| constants.tag == match_settings.movement_profile)
+            else {
+                continue;
+            };
+            shared::bevy::log::info!("Loaded movement profile: {:?}", constants);
+
+            movement_profile.0 = constants.clone();
+            for mut character_constants in characters.iter_mut() {
+                *character_constants = constants.clone();
+            }
+
+            let Some(endpoint) = server.get_endpoint_mut() else {
+                continue;
+            };
+            if let Err(err) = endpoint.send_group_message_on(
+                app.client_ids().iter(),
+                ServerChannels::ImportantData,
+                ServerMessage::MovementProfile(constants.clone()),
+            ) {
+                shared::bevy::log::error!("Failed to send movement profile: {}", err);
+            }
+        }
+    }
+}
+
+/// Sends the match state to everyone whenever it changes.
+pub fn send_match_state_system(
+    app: Res<Application>,
+    mut server: ResMut<QuinnetServer>,
+    match_state: Res<MatchState>,
+    mut last_sent: Local<Option<MatchState>>,
+) {
+    if last_sent.as_ref() == Some(&*match_state) {
+        return;
+    }
+
+    if let Err(err) = server.endpoint_mut().send_group_message_on(
+        app.client_ids().iter(),
+        ServerChannels::GameEvents,
+        ServerMessage::MatchState(match_state.clone()),
+    ) {
+        shared::bevy::log::error!("Failed to send match state: {}", err);
+    }
+    *last_sent = Some(match_state.clone());
+}
+
+pub fn broadcast_kills_system(
+    app: Res<Application>,
+    mut server: ResMut<QuinnetServer>,
+    mut death_events: EventReader<CharacterDeathEvent>,
+) {
+    let endpoint = server.endpoint_mut();
+    let clients = app.client_ids();
+    for event in death_events.read() {
+        if let Err(err) = endpoint.send_group_message_on(
+            clients.iter(),
+            ServerChannels::GameEvents,
+            ServerMessage::Kill {
+                killer_client_id: event.killer_client_id,
+                victim_client_id: event.victim_client_id,
+                weapon_config_tag: event.weapon_config_tag.clone(),
+            },
+        ) {
+            shared::bevy::log::error!("Failed to send kill: {}", err);
+        }
+    }
+}
+
+/// Copies everyone's round trip time to the scoreboard every few seconds, more often would
+/// resend the match state all the time.
+pub fn update_pings_system(
+    time: Res<Time>,
+    mut since_update: Local<f32>,
+    server: Res<QuinnetServer>,
+    mut match_state: ResMut<MatchState>,
+) {
+    *since_update += time.delta_seconds();
+    if *since_update < PING_UPDATE_SECONDS {
+        return;
+    }
+    *since_update = 0.0;
+
+    let endpoint = server.endpoint();
+    for player in &mut match_state.players {
+        if let Some(stats) = endpoint.connection_stats(player.client_id) {
+            player.ping_ms = stats.path.rtt.as_millis() as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taken_usernames_get_a_number() {
+        let taken = ["Unnamed Player", "unnamed player (2)"];
+        let is_taken = |username: &str| {
+            taken
+                .iter()
+                .any(|taken| taken.eq_ignore_ascii_case(username))
+        };
+        assert_eq!(unique_username("Someone", is_taken), "Someone");
+        assert_eq!(
+            unique_username("Unnamed Player", is_taken),
+            "Unnamed Player (3)"
+        );
+
+        let long = "x".repeat(MAX_USERNAME_LENGTH);
+        let numbered = unique_username(&long, |username| username == long);
+        assert_eq!(numbered.chars().count(), MAX_USERNAME_LENGTH);
+        assert!(numbered.ends_with(" (2)"));
+    }
+}
@@ -0,0 +1,53 @@
+use shared::bevy::prelude::*;
+use shared::character::{CharacterCollider, CharacterState};
+use shared::pickups::WeaponPickup;
+use shared::resources::DataAssetHandles;
+use shared::weapons::{get_weapon_config, WeaponConfig, WeaponInventory};
+
+pub fn pickup_system(
+    fixed_time: Res<Time<Fixed>>,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    mut characters: Query<(
+        &CharacterState,
+        &CharacterCollider,
+        &Transform,
+        &mut WeaponInventory,
+    )>,
+    mut pickups: Query<(&mut WeaponPickup, &Transform), Without<CharacterState>>,
+) {
+    for (mut pickup, pickup_xform) in pickups.iter_mut() {
+        if !pickup.is_available() {
+            pickup.respawn_remaining -= fixed_time.delta_seconds();
+            continue;
+        }
+
+        let Some(weapon_config) = get_weapon_config(
+            &data_asset_handles,
+            &weapon_configs,
+            &pickup.weapon_config_tag,
+        ) else {
+            continue;
+        };
+
+        if let Some((char_state, _, _, mut inventory)) =
+            characters
+                .iter_mut()
+                .find(|(_, char_collider, char_xform, _)| {
+                    pickup.overlaps(
+                        pickup_xform.translation,
+                        char_xform.translation,
+                        char_collider,
+                    )
+                })
+        {
+            inventory.give(weapon_config, pickup.ammo);
+            pickup.respawn_remaining = pickup.respawn_seconds;
+            shared::bevy::log::info!(
+                "Client {} picked up {}",
+                char_state.owner_client_id,
+                pickup.weapon_config_tag
+            );
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use shared::avian3d::spatial_query::SpatialQuery;
+use shared::bevy::prelude::*;
+use shared::character::{CharacterCollider, CharacterObstacle, CharacterState};
+use shared::projectiles::*;
+use shared::resources::DataAssetHandles;
+use shared::weapons::{get_weapon_config, WeaponConfig, WeaponFiredProjectileEvent};
+
+use crate::events::ProjectileImpactEvent;
+
+pub fn spawn_projectile_system(
+    mut commands: Commands,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    mut projectile_events: EventReader<WeaponFiredProjectileEvent>,
+) {
+    for event in projectile_events.read() {
+        if let Some(weapon_config) = get_weapon_config(
+            &data_asset_handles,
+            &weapon_configs,
+            &event.weapon_config_tag,
+        ) {
+            spawn_projectile(
+                &mut commands,
+                event.owner_client_id,
+                weapon_config,
+                event.fired_input_id,
+                event.origin,
+                event.direction,
+            );
+        }
+    }
+}
+
+pub fn simulate_projectiles_system(
+    mut commands: Commands,
+    fixed_time: Res<Time<Fixed>>,
+    spatial_query: SpatialQuery,
+    characters: Query<(&CharacterState, &CharacterCollider, &Transform), Without<Projectile>>,
+    mut projectiles: Query<(Entity, &mut Projectile, &mut Transform)>,
+    mut impact_events: EventWriter<ProjectileImpactEvent>,
+) {
+    let character_obstacles = characters
+        .iter()
+        .map(|(char_state, char_collider, char_xform)| {
+            CharacterObstacle::new(
+                char_state.owner_client_id,
+                char_xform.translation,
+                char_collider,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (entity, mut projectile, mut projectile_xform) in projectiles.iter_mut() {
+        let impact = move_projectile(
+            &spatial_query,
+            &mut projectile,
+            &mut projectile_xform,
+            &character_obstacles,
+            fixed_time.delta_seconds(),
+        );
+
+        if let Some(impact) = impact {
+            impact_events.send(ProjectileImpactEvent {
+                owner_client_id: projectile.owner_client_id,
+                weapon_config_tag: projectile.weapon_config_tag.clone(),
+                position: impact.position(),
+                direct_hit_client_id: match impact {
+                    ProjectileImpact::Character { client_id, .. } => Some(client_id),
+                    ProjectileImpact::World { .. } => None,
+                },
+            });
+            commands.entity(entity).despawn_recursive();
+        } else if projectile.is_expired() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
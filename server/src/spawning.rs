@@ -0,0 +1,213 @@
+use shared::bevy::prelude::*;
+use shared::character::*;
+use shared::map::{CurrentMap, MapConfig};
+use shared::resources::DataAssetHandles;
+use shared::resources::MovementProfile;
+use shared::weapons::{
+    get_weapon_config, WeaponConfig, WeaponFiredProjectileEvent, WeaponInventory, WeaponState,
+};
+
+use crate::events::{CharacterDeathEvent, LagCompensatedShotEvent};
+use crate::net::Application;
+
+const RESPAWN_DELAY_SECONDS: f32 = 3.0;
+const SPAWN_PROTECTION_SECONDS: f32 = 2.0;
+// used when the map doesn't have any spawn points
+const FALLBACK_SPAWN_POSITION: Vec3 = Vec3::new(0.0, 2.0, 0.0);
+// everyone starts out with this weapon, fully loaded
+const STARTING_WEAPON: &str = "rocket_launcher";
+
+#[derive(Resource, Default)]
+pub struct SpawnState {
+    respawns: Vec<PendingRespawn>,
+    /// Counts spawns so safe spawn points are taken in turn.
+    spawn_count: usize,
+}
+
+impl SpawnState {
+    pub fn queue_respawn(&mut self, client_id: u64, delay_seconds: f32) {
+        self.respawns.push(PendingRespawn {
+            client_id,
+            remaining_seconds: delay_seconds,
+        });
+    }
+}
+
+struct PendingRespawn {
+    client_id: u64,
+    remaining_seconds: f32,
+}
+
+/// Freshly spawned characters can't be damaged until this runs out or they fire.
+#[derive(Component)]
+pub struct SpawnProtection {
+    pub remaining_seconds: f32,
+}
+
+/// Characters on different teams are enemies, without teams everyone is.
+fn is_enemy(team: Option<u8>, other_team: Option<u8>) -> bool {
+    team.is_none() || team != other_team
+}
+
+pub fn queue_respawn_system(
+    mut spawn_state: ResMut<SpawnState>,
+    mut death_events: EventReader<CharacterDeathEvent>,
+) {
+    for event in death_events.read() {
+        spawn_state.queue_respawn(event.victim_client_id, RESPAWN_DELAY_SECONDS);
+    }
+}
+
+pub fn respawn_timer_system(
+    fixed_time: Res<Time<Fixed>>,
+    app: Res<Application>,
+    mut spawn_state: ResMut<SpawnState>,
+    characters: Query<&CharacterState>,
+    mut spawn_events: EventWriter<CharacterSpawnEvent>,
+) {
+    spawn_state.respawns.retain_mut(|respawn| {
+        // nobody to respawn if they left while dead
+        if app.clients.iter().all(|c| c.client_id != respawn.client_id) {
+            return false;
+        }
+        respawn.remaining_seconds -= fixed_time.delta_seconds();
+        if respawn.remaining_seconds > 0.0 {
+            return true;
+        }
+        // a despawned character is only gone at the end of the tick, the spawn waits for that
+        if characters
+            .iter()
+            .any(|char_state| char_state.owner_client_id == respawn.client_id)
+        {
+            return true;
+        }
+        spawn_events.send(CharacterSpawnEvent {
+            client_id: respawn.client_id,
+        });
+        false
+    });
+}
+
+/// What a character spawns with, empty until the weapon configs are loaded.
+pub fn starting_inventory(
+    data_asset_handles: &DataAssetHandles,
+    weapon_configs: &Assets<WeaponConfig>,
+) -> WeaponInventory {
+    WeaponInventory {
+        weapons: get_weapon_config(data_asset_handles, weapon_configs, STARTING_WEAPON)
+            .map(|config| WeaponState::new(config, config.magazine_size + config.max_ammo))
+            .into_iter()
+            .collect(),
+        active_slot: 0,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_system(
+    mut commands: Commands,
+    app: Res<Application>,
+    data_asset_handles: Res<DataAssetHandles>,
+    weapon_configs: Res<Assets<WeaponConfig>>,
+    movement_profile: Res<MovementProfile>,
+    maps: Res<Assets<MapConfig>>,
+    current_map: Res<CurrentMap>,
+    mut spawn_state: ResMut<SpawnState>,
+    characters: Query<(&CharacterState, &CharacterCollider, &Transform)>,
+    mut spawn_events: EventReader<CharacterSpawnEvent>,
+) {
+    let team_of = |client_id: u64| {
+        app.clients
+            .iter()
+            .find(|c| c.client_id == client_id)
+            .and_then(|c| c.team)
+    };
+
+    // characters spawned this tick aren't in the query yet, so keep track of them as well
+    let mut others: Vec<(CharacterObstacle, Option<u8>)> = characters
+        .iter()
+        .map(|(char_state, char_collider, char_xform)| {
+            (
+                CharacterObstacle::new(
+                    char_state.owner_client_id,
+                    char_xform.translation,
+                    char_collider,
+                ),
+                team_of(char_state.owner_client_id),
+            )
+        })
+        .collect();
+
+    for event in spawn_events.read() {
+        let Some(client) = app.clients.iter().find(|c| c.client_id == event.client_id) else {
+            continue;
+        };
+        if others
+            .iter()
+            .any(|(other, _)| other.client_id == event.client_id)
+        {
+            continue;
+        }
+
+        let collider = CharacterCollider::new(movement_profile.radius, movement_profile.height);
+        let obstacles: Vec<CharacterObstacle> =
+            others.iter().map(|(other, _)| other.clone()).collect();
+        let enemy_positions: Vec<Vec3> = others
+            .iter()
+            .filter(|(_, other_team)| is_enemy(client.team, *other_team))
+            .map(|(other, _)| other.position)
+            .collect();
+        let (position, yaw) = maps
+            .get(&current_map.handle)
+            .and_then(|map| {
+                map.select_spawn_point(
+                    client.team,
+                    &collider,
+                    &obstacles,
+                    &enemy_positions,
+                    spawn_state.spawn_count,
+                )
+            })
+            .map(|spawn_point| (spawn_point.position, spawn_point.yaw))
+            .unwrap_or((FALLBACK_SPAWN_POSITION, 0.0));
+        spawn_state.spawn_count += 1;
+
+        let entity = spawn_character(
+            &mut commands,
+            event.client_id,
+            &position,
+            yaw,
+            &movement_profile,
+            starting_inventory(&data_asset_handles, &weapon_configs),
+        );
+        commands.entity(entity).insert(SpawnProtection {
+            remaining_seconds: SPAWN_PROTECTION_SECONDS,
+        });
+        others.push((
+            CharacterObstacle::new(event.client_id, position, &collider),
+            client.team,
+        ));
+        shared::bevy::log::info!("Client {} spawned at {}", event.client_id, position);
+    }
+}
+
+pub fn spawn_protection_system(
+    mut commands: Commands,
+    fixed_time: Res<Time<Fixed>>,
+    mut characters: Query<(Entity, &CharacterState, &mut SpawnProtection)>,
+    mut shot_events: EventReader<LagCompensatedShotEvent>,
+    mut projectile_events: EventReader<WeaponFiredProjectileEvent>,
+) {
+    // firing gives up the protection straight away
+    let shooters: Vec<u64> = shot_events
+        .read()
+        .map(|event| event.shooter_client_id)
+        .chain(projectile_events.read().map(|event| event.owner_client_id))
+        .collect();
+
+    for (entity, char_state, mut protection) in characters.iter_mut() {
+        protection.remaining_seconds -= fixed_time.delta_seconds();
+        if protection.remaining_seconds <= 0.0 || shooters.contains(&char_state.owner_client_id) {
+            commands.entity(entity).remove::<SpawnProtection>();
+        }
+    }
+}
@@ -1,21 +1,106 @@
-use crate::{protocol::CharacterSnapshot, weapons::WeaponState};
+use crate::{
+    protocol::{CharacterSnapshot, PlayerInput},
+    weapons::WeaponInventory,
+};
 use avian3d::prelude::*;
 use bevy::prelude::*;
 use bevy_quinnet::shared::ClientId;
+use serde::{Deserialize, Serialize};
 
 const GRAVITY: f32 = 9.81;
+pub const CHARACTER_RADIUS: f32 = 0.5;
+pub const CHARACTER_HEIGHT: f32 = 1.0;
+pub const MAX_HEALTH: u32 = 100;
+
+// fraction of incoming damage that armor soaks up while it lasts
+const ARMOR_ABSORPTION: f32 = 0.66;
 
 #[derive(Event)]
 pub struct CharacterDespawnEvent {
     pub client_id: u64,
 }
 
-#[derive(Component)]
+/// Asks for a character to be spawned for a client, at a spawn point picked for them.
+#[derive(Event)]
+pub struct CharacterSpawnEvent {
+    pub client_id: u64,
+}
+
+/// How a character is shaped and moves, loaded from `*.character.ron` files.
+///
+/// The server picks one of these per match and sends it to clients so their prediction
+/// moves the same way.
+#[derive(Asset, TypePath, Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct CharacterConstants {
-    pub move_drag: f32,
-    pub move_accel: f32,
+    pub tag: String,
+    pub radius: f32,
+    pub height: f32,
+    pub crouch_height: f32,
+    /// How far above the character's origin the eyes are when standing, shots and the camera
+    /// start from here.
+    pub eye_height: f32,
     pub move_speed: f32,
+    /// Move speed while crouched.
+    pub crouch_speed: f32,
+    pub ground_accel: f32,
+    pub air_accel: f32,
+    /// Air acceleration only adds speed up to this much in the wished direction. Keeping it low
+    /// while `air_accel` is high is what makes air strafing gain speed.
+    pub air_speed_cap: f32,
+    pub friction: f32,
+    /// Below this speed friction acts as if we were going this fast, so slow characters stop
+    /// quickly instead of creeping to a halt.
+    pub stop_speed: f32,
+    /// How high a jump from standing still goes, in meters.
+    pub jump_height: f32,
     pub max_ground_distance: f32,
+    /// Obstacles up to this tall are walked over, it's also how far down we snap to the ground.
+    pub step_height: f32,
+    /// The steepest ground that can be stood on, anything steeper is slid down.
+    pub max_slope_degrees: f32,
+}
+
+/// Only used until the server's profile is loaded, matches `default.character.ron`.
+impl Default for CharacterConstants {
+    fn default() -> Self {
+        CharacterConstants {
+            tag: "default".to_string(),
+            radius: CHARACTER_RADIUS,
+            height: CHARACTER_HEIGHT,
+            crouch_height: 0.6,
+            eye_height: 0.4,
+            move_speed: 5.0,
+            crouch_speed: 2.5,
+            ground_accel: 15.5,
+            air_accel: 15.5,
+            air_speed_cap: 5.0,
+            friction: 5.9,
+            stop_speed: 0.0,
+            jump_height: 1.0,
+            max_ground_distance: 0.1,
+            step_height: 0.3,
+            max_slope_degrees: 45.0,
+        }
+    }
+}
+
+impl CharacterConstants {
+    pub fn height_for(&self, is_crouching: bool) -> f32 {
+        if is_crouching {
+            self.crouch_height
+        } else {
+            self.height
+        }
+    }
+
+    /// The upwards speed that reaches `jump_height` under gravity.
+    pub fn jump_speed(&self) -> f32 {
+        (2.0 * GRAVITY * self.jump_height).sqrt()
+    }
+
+    pub fn is_walkable(&self, ground_normal: Vec3) -> bool {
+        ground_normal.y >= self.max_slope_degrees.to_radians().cos()
+    }
 }
 
 #[derive(Component)]
@@ -24,13 +109,36 @@ pub struct CharacterState {
     pub velocity: Vec3,
     pub visuals_offset: Vec3,
     pub is_grounded: bool,
+    pub is_crouching: bool,
 }
 
 impl CharacterState {
+    pub fn height(&self, constants: &CharacterConstants) -> f32 {
+        constants.height_for(self.is_crouching)
+    }
+
+    /// The eyes stay the same distance below the top of the character when crouched.
+    pub fn eye_height(&self, constants: &CharacterConstants) -> f32 {
+        constants.eye_height - (constants.height - self.height(constants)) / 2.0
+    }
+
+    pub fn eye_position(&self, constants: &CharacterConstants, transform: &Transform) -> Vec3 {
+        transform.translation + Vec3::Y * self.eye_height(constants)
+    }
+
     pub fn is_locally_controlled(&self, local_peer_id: ClientId) -> bool {
         self.owner_client_id == local_peer_id
     }
 
+    /// Pushes the character, it's treated as airborne so ground friction doesn't eat the impulse
+    /// before the next move gets a chance to lift it off the ground.
+    pub fn apply_impulse(&mut self, impulse: Vec3) {
+        self.velocity += impulse;
+        if impulse.y > 0.0 {
+            self.is_grounded = false;
+        }
+    }
+
     pub fn apply_snapshot(
         &mut self,
         snapshot: &CharacterSnapshot,
@@ -42,6 +150,132 @@ impl CharacterState {
         if let Some(position) = snapshot.position {
             existing_transform.translation = position;
         }
+        if let Some(is_crouching) = snapshot.is_crouching {
+            self.is_crouching = is_crouching;
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct CharacterHealth {
+    pub health: u32,
+    pub armor: u32,
+}
+
+impl CharacterHealth {
+    pub fn is_dead(&self) -> bool {
+        self.health == 0
+    }
+
+    /// Applies damage, armor absorbs part of it first. Returns true if this killed the character.
+    pub fn take_damage(&mut self, amount: u32) -> bool {
+        if self.is_dead() {
+            return false;
+        }
+
+        let absorbed = ((amount as f32 * ARMOR_ABSORPTION).round() as u32).min(self.armor);
+        self.armor -= absorbed;
+        self.health = self.health.saturating_sub(amount - absorbed);
+        self.is_dead()
+    }
+
+    pub fn apply_snapshot(&mut self, snapshot: &CharacterSnapshot) {
+        if let Some(health) = snapshot.health {
+            self.health = health;
+        }
+        if let Some(armor) = snapshot.armor {
+            self.armor = armor;
+        }
+    }
+}
+
+/// The upright cylinder a character moves, stands and gets hit with.
+///
+/// It's resized when crouching or a new movement profile changes the character's height.
+#[derive(Component, Clone)]
+pub struct CharacterCollider {
+    pub radius: f32,
+    pub height: f32,
+    pub shape: Collider,
+}
+
+impl CharacterCollider {
+    pub fn new(radius: f32, height: f32) -> Self {
+        CharacterCollider {
+            radius,
+            height,
+            shape: Collider::cylinder(radius, height),
+        }
+    }
+
+    pub fn for_state(state: &CharacterState, constants: &CharacterConstants) -> Self {
+        CharacterCollider::new(constants.radius, state.height(constants))
+    }
+
+    pub fn half_height(&self) -> f32 {
+        self.height / 2.0
+    }
+
+    /// Rebuilds the shape if the character changed size.
+    pub fn update(&mut self, state: &CharacterState, constants: &CharacterConstants) {
+        let height = state.height(constants);
+        if self.radius != constants.radius || self.height != height {
+            *self = CharacterCollider::new(constants.radius, height);
+        }
+    }
+
+    /// Whether another upright cylinder overlaps this one.
+    pub fn overlaps(
+        &self,
+        position: Vec3,
+        other_position: Vec3,
+        other_radius: f32,
+        other_half_height: f32,
+    ) -> bool {
+        let offset = other_position - position;
+        offset.xz().length() < self.radius + other_radius
+            && offset.y.abs() < self.half_height() + other_half_height
+    }
+}
+
+/// Another character in the way of a move, as it was at the start of the tick.
+#[derive(Clone)]
+pub struct CharacterObstacle {
+    pub client_id: ClientId,
+    pub position: Vec3,
+    pub radius: f32,
+    pub height: f32,
+}
+
+impl CharacterObstacle {
+    pub fn new(client_id: ClientId, position: Vec3, collider: &CharacterCollider) -> Self {
+        CharacterObstacle {
+            client_id,
+            position,
+            radius: collider.radius,
+            height: collider.height,
+        }
+    }
+
+    /// Whether a character at `position` is inside this one, just touching doesn't count.
+    fn penetrates(&self, collider: &CharacterCollider, position: Vec3) -> bool {
+        let skin = 0.01;
+        collider.overlaps(
+            position,
+            self.position,
+            self.radius - skin,
+            self.height / 2.0 - skin,
+        )
+    }
+}
+
+/// Keeps colliders in step with crouching and movement profile changes that didn't come from a
+/// move, like snapshots and hot-reloaded profiles.
+pub fn update_character_colliders_system(
+    mut characters: Query<(&CharacterState, &CharacterConstants, &mut CharacterCollider)>,
+) {
+    for (state, constants, mut collider) in characters.iter_mut() {
+        collider.update(state, constants);
     }
 }
 
@@ -54,6 +288,9 @@ pub fn spawn_character(
     commands: &mut Commands,
     owner_peer_id: ClientId,
     position: &Vec3,
+    yaw: f32,
+    constants: &CharacterConstants,
+    inventory: WeaponInventory,
 ) -> Entity {
     commands
         .spawn((
@@ -62,55 +299,92 @@ pub fn spawn_character(
                 velocity: Vec3::ZERO,
                 visuals_offset: Vec3::ZERO,
                 is_grounded: false,
+                is_crouching: false,
             },
-            CharacterConstants {
-                move_drag: 5.9,
-                move_accel: 15.5,
-                move_speed: 5.0,
-                max_ground_distance: 0.1,
-            },
-            WeaponState {
-                weapon_config_tag: "rocket_launcher".to_string(),
-                next_fire_time: 0,
-                ammo: 100,
+            constants.clone(),
+            CharacterCollider::new(constants.radius, constants.height),
+            CharacterHealth {
+                health: MAX_HEALTH,
+                armor: 0,
             },
+            inventory,
             SpatialBundle {
-                transform: Transform::from_translation(position.clone()),
+                transform: Transform::from_translation(*position)
+                    .with_rotation(Quat::from_rotation_y(yaw)),
                 ..default()
             },
         ))
         .id()
 }
 
+/// Moves a character for one input.
+///
+/// Other characters are solid but never pushed. `obstacles` should hold every character as it
+/// was before anyone moved this tick, the moving character itself is skipped, so the result
+/// doesn't depend on the order characters are moved in.
+#[allow(clippy::too_many_arguments)]
 pub fn move_character(
-    wish_dir: Vec3,
+    input: &PlayerInput,
     spatial_query: &SpatialQuery,
+    obstacles: &[CharacterObstacle],
     state: &mut CharacterState,
+    collider: &mut CharacterCollider,
     transform: &mut Transform,
     constants: &CharacterConstants,
     delta_seconds: f32,
 ) {
+    update_crouch(input.crouch, spatial_query, state, transform, constants);
+    collider.update(state, constants);
+
+    let obstacles = obstacles
+        .iter()
+        .filter(|obstacle| obstacle.client_id != state.owner_client_id)
+        .collect::<Vec<_>>();
+    separate_from_characters(spatial_query, &obstacles, state, collider, transform);
+
+    let wish_dir = input.compute_wish_dir();
+    let wish_speed = if state.is_crouching {
+        constants.crouch_speed
+    } else {
+        constants.move_speed
+    };
     let mut velocity = state.velocity;
-    let radius = 0.5;
-    let height = 1.0;
-    let collider = Collider::cylinder(radius, height);
     let epsilon = 0.0001;
 
-    // Apply acceleration
-    velocity += accelerate(
-        wish_dir,
-        constants.move_speed,
-        velocity.dot(wish_dir),
-        constants.move_accel,
-        delta_seconds,
-    );
+    if state.is_grounded {
+        // no friction on the tick we jump, so jumping again right as we land keeps our speed
+        if input.jump {
+            velocity.y = constants.jump_speed();
+            state.is_grounded = false;
+        } else {
+            velocity = apply_friction(velocity, constants, delta_seconds);
+        }
+    }
 
     if state.is_grounded {
-        velocity *= 1.0 - constants.move_drag * delta_seconds;
+        velocity += accelerate(
+            wish_dir,
+            wish_speed,
+            wish_speed,
+            velocity.dot(wish_dir),
+            constants.ground_accel,
+            delta_seconds,
+        );
     } else {
+        velocity += accelerate(
+            wish_dir,
+            wish_speed,
+            constants.air_speed_cap,
+            velocity.dot(wish_dir),
+            constants.air_accel,
+            delta_seconds,
+        );
         velocity.y -= GRAVITY * delta_seconds;
     }
 
+    // only characters that stayed on the ground get snapped back down to it
+    let snap_to_ground = state.is_grounded;
+
     let mut remaining_time = delta_seconds;
     let max_iterations = 4;
 
@@ -120,22 +394,63 @@ pub fn move_character(
         }
 
         let move_delta = velocity * remaining_time;
+        let move_distance = move_delta.length();
+        let Ok(move_direction) = Dir3::new(move_delta) else {
+            break;
+        };
 
-        if let Some(hit) = spatial_query.cast_shape(
-            &collider,
+        let world_hit = spatial_query
+            .cast_shape(
+                &collider.shape,
+                transform.translation,
+                Quat::IDENTITY,
+                move_direction,
+                move_distance,
+                true,
+                SpatialQueryFilter::default(),
+            )
+            .map(|hit| MoveHit {
+                time_of_impact: hit.time_of_impact,
+                normal: hit.normal1,
+                is_character: false,
+            });
+        let character_hit = cast_characters(
+            &obstacles,
+            collider,
             transform.translation,
-            transform.rotation,
-            Dir3::new(move_delta.normalize_or_zero()).unwrap_or(Dir3::Z),
-            move_delta.length(),
-            true,
-            SpatialQueryFilter::default(),
-        ) {
-            let normal = hit.normal1;
-            let distance_before_hit = hit.time_of_impact * move_delta.length();
-            let time_before_hit = hit.time_of_impact * remaining_time;
+            move_direction,
+            move_distance,
+        );
+        let closest_hit = match (world_hit, character_hit) {
+            (Some(world_hit), Some(character_hit)) => {
+                if character_hit.time_of_impact < world_hit.time_of_impact {
+                    Some(character_hit)
+                } else {
+                    Some(world_hit)
+                }
+            }
+            (world_hit, character_hit) => world_hit.or(character_hit),
+        };
+
+        if let Some(hit) = closest_hit {
+            let normal = hit.normal;
+
+            // walk up anything short enough instead of sliding along it, but not other characters
+            if state.is_grounded && !hit.is_character && !constants.is_walkable(normal) {
+                if let Some(stepped_translation) = step_up(
+                    spatial_query,
+                    &collider.shape,
+                    transform.translation,
+                    move_delta,
+                    constants,
+                ) {
+                    transform.translation = stepped_translation;
+                    break;
+                }
+            }
 
             // Move to just before the collision point
-            transform.translation += move_delta.normalize() * distance_before_hit;
+            transform.translation += move_direction * hit.time_of_impact;
 
             // Blend reflection and velocity to create a slight bounce effect, reducing the "stickiness" of walls
             let reflection = velocity - 2.0 * velocity.dot(normal) * normal;
@@ -148,7 +463,7 @@ pub fn move_character(
             // Prevent sticking to walls
             transform.translation += normal * epsilon;
 
-            remaining_time -= time_before_hit;
+            remaining_time -= remaining_time * (hit.time_of_impact / move_distance);
         } else {
             // No collision, move the full distance
             transform.translation += move_delta;
@@ -156,45 +471,258 @@ pub fn move_character(
         }
     }
 
-    let ground_info = ground_check(
+    let mut ground_info = ground_check(
         spatial_query,
         transform,
-        &collider,
+        &collider.shape,
         constants.max_ground_distance,
     );
 
-    if ground_info.is_some() {
-        state.is_grounded = true;
-    } else {
-        state.is_grounded = false;
+    // keep walking characters on the ground when going down stairs and ramps,
+    // rather than letting them fly off every edge
+    if snap_to_ground && ground_info.is_none() {
+        if let Some(snap_ground) = ground_check(
+            spatial_query,
+            transform,
+            &collider.shape,
+            constants.step_height,
+        )
+        .filter(|ground| constants.is_walkable(ground.normal))
+        {
+            transform.translation.y -= snap_ground.distance - epsilon;
+            velocity.y = velocity.y.min(0.0);
+            ground_info = Some(snap_ground);
+        }
     }
 
+    state.is_grounded = ground_info.is_some_and(|ground| constants.is_walkable(ground.normal));
     state.velocity = velocity;
 }
 
+struct MoveHit {
+    time_of_impact: f32,
+    normal: Vec3,
+    is_character: bool,
+}
+
+/// Sweeps the collider against other characters. Each one is grown by our own size, so a ray
+/// from our center finds the same hit a cylinder cast would.
+fn cast_characters(
+    obstacles: &[&CharacterObstacle],
+    collider: &CharacterCollider,
+    translation: Vec3,
+    direction: Dir3,
+    max_distance: f32,
+) -> Option<MoveHit> {
+    obstacles
+        .iter()
+        // a cast starting inside would never let us leave, overlaps are separated instead
+        .filter(|obstacle| !obstacle.penetrates(collider, translation))
+        .filter_map(|obstacle| {
+            Collider::cylinder(
+                obstacle.radius + collider.radius,
+                obstacle.height + collider.height,
+            )
+            .cast_ray(
+                obstacle.position,
+                Quat::IDENTITY,
+                translation,
+                direction.as_vec3(),
+                max_distance,
+                true,
+            )
+            .map(|(time_of_impact, normal)| MoveHit {
+                time_of_impact,
+                normal,
+                is_character: true,
+            })
+        })
+        .min_by(|a, b| a.time_of_impact.total_cmp(&b.time_of_impact))
+}
+
+/// Moves half of the way out of every character we overlap, they do the same on their own move.
+///
+/// Characters on the exact same spot are split along X by client id, so both sides agree on
+/// which way to go.
+fn separate_from_characters(
+    spatial_query: &SpatialQuery,
+    obstacles: &[&CharacterObstacle],
+    state: &CharacterState,
+    collider: &CharacterCollider,
+    transform: &mut Transform,
+) {
+    let mut push = Vec3::ZERO;
+    for obstacle in obstacles {
+        if !obstacle.penetrates(collider, transform.translation) {
+            continue;
+        }
+
+        let offset = (transform.translation - obstacle.position).xz();
+        let direction = if offset.length_squared() > 0.0001 {
+            offset.normalize()
+        } else if state.owner_client_id < obstacle.client_id {
+            Vec2::X
+        } else {
+            Vec2::NEG_X
+        };
+        let overlap = collider.radius + obstacle.radius - offset.length();
+        push += Vec3::new(direction.x, 0.0, direction.y) * overlap / 2.0;
+    }
+
+    let Ok(push_direction) = Dir3::new(push) else {
+        return;
+    };
+    // walls still win over other characters
+    let push_distance = spatial_query
+        .cast_shape(
+            &collider.shape,
+            transform.translation,
+            Quat::IDENTITY,
+            push_direction,
+            push.length(),
+            true,
+            SpatialQueryFilter::default(),
+        )
+        .map(|hit| hit.time_of_impact)
+        .unwrap_or(push.length());
+    transform.translation += push_direction * push_distance;
+}
+
+/// Tries to move over an obstacle by lifting the character up to `step_height`,
+/// moving it forward and putting it back down. Returns where the character ends up
+/// if it landed on walkable ground.
+fn step_up(
+    spatial_query: &SpatialQuery,
+    collider: &Collider,
+    translation: Vec3,
+    move_delta: Vec3,
+    constants: &CharacterConstants,
+) -> Option<Vec3> {
+    let epsilon = 0.0001;
+    let horizontal_delta = Vec3::new(move_delta.x, 0.0, move_delta.z);
+    let horizontal_direction = Dir3::new(horizontal_delta).ok()?;
+
+    // go up, but not through a ceiling
+    let up_distance = spatial_query
+        .cast_shape(
+            collider,
+            translation,
+            Quat::IDENTITY,
+            Dir3::Y,
+            constants.step_height,
+            true,
+            SpatialQueryFilter::default(),
+        )
+        .map(|hit| (hit.time_of_impact - epsilon).max(0.0))
+        .unwrap_or(constants.step_height);
+    if up_distance <= epsilon {
+        return None;
+    }
+    let raised_translation = translation + Vec3::Y * up_distance;
+
+    // go forward from up there, if we didn't get anywhere the obstacle is too tall
+    let forward_distance = spatial_query
+        .cast_shape(
+            collider,
+            raised_translation,
+            Quat::IDENTITY,
+            horizontal_direction,
+            horizontal_delta.length(),
+            true,
+            SpatialQueryFilter::default(),
+        )
+        .map(|hit| (hit.time_of_impact - epsilon).max(0.0))
+        .unwrap_or(horizontal_delta.length());
+    if forward_distance <= epsilon {
+        return None;
+    }
+    let forward_translation = raised_translation + horizontal_direction * forward_distance;
+
+    // and back down onto whatever we stepped on
+    let landing = spatial_query.cast_shape(
+        collider,
+        forward_translation,
+        Quat::IDENTITY,
+        Dir3::NEG_Y,
+        up_distance,
+        true,
+        SpatialQueryFilter::default(),
+    )?;
+    if !constants.is_walkable(landing.normal1) {
+        return None;
+    }
+
+    Some(forward_translation - Vec3::Y * (landing.time_of_impact - epsilon).max(0.0))
+}
+
+/// Crouches or stands up, standing up waits until there is room for it.
+///
+/// On the ground the feet stay put and the head moves, in the air the legs are pulled up
+/// instead, which is what lets a crouch-jump clear higher ledges.
+fn update_crouch(
+    wants_crouch: bool,
+    spatial_query: &SpatialQuery,
+    state: &mut CharacterState,
+    transform: &mut Transform,
+    constants: &CharacterConstants,
+) {
+    let half_height_change = (constants.height - constants.crouch_height) / 2.0;
+
+    if wants_crouch && !state.is_crouching {
+        state.is_crouching = true;
+        if state.is_grounded {
+            transform.translation.y -= half_height_change;
+        } else {
+            transform.translation.y += half_height_change;
+        }
+    } else if !wants_crouch && state.is_crouching {
+        let standing_collider = Collider::cylinder(constants.radius, constants.height);
+        // in the air we try to put the legs back down first, but near the ground that
+        // would put them in the floor so we stand up the way we would on the ground
+        let offsets = if state.is_grounded {
+            vec![half_height_change]
+        } else {
+            vec![-half_height_change, half_height_change]
+        };
+
+        let free_offset = offsets.into_iter().find(|offset| {
+            spatial_query
+                .shape_intersections(
+                    &standing_collider,
+                    transform.translation + Vec3::Y * *offset,
+                    Quat::IDENTITY,
+                    SpatialQueryFilter::default(),
+                )
+                .is_empty()
+        });
+
+        if let Some(offset) = free_offset {
+            state.is_crouching = false;
+            transform.translation.y += offset;
+        }
+    }
+}
+
 fn ground_check(
     spatial_query: &SpatialQuery,
     transform: &Transform,
     collider: &Collider,
     max_ground_distance: f32,
 ) -> Option<GroundInfo> {
-    let down_ray = -transform.up();
-    if let Some(hit) = spatial_query.cast_shape(
-        collider,
-        transform.translation,
-        transform.rotation,
-        down_ray,
-        max_ground_distance,
-        true,
-        SpatialQueryFilter::default(),
-    ) {
-        Some(GroundInfo {
+    spatial_query
+        .cast_shape(
+            collider,
+            transform.translation,
+            Quat::IDENTITY,
+            Dir3::NEG_Y,
+            max_ground_distance,
+            true,
+            SpatialQueryFilter::default(),
+        )
+        .map(|hit| GroundInfo {
             normal: hit.normal1,
             distance: hit.time_of_impact,
         })
-    } else {
-        None
-    }
 }
 
 struct GroundInfo {
@@ -202,14 +730,29 @@ struct GroundInfo {
     distance: f32,
 }
 
+fn apply_friction(velocity: Vec3, constants: &CharacterConstants, delta_seconds: f32) -> Vec3 {
+    let speed = velocity.length();
+    if speed < 0.0001 {
+        return velocity;
+    }
+
+    let drop = speed.max(constants.stop_speed) * constants.friction * delta_seconds;
+    velocity * ((speed - drop).max(0.0) / speed)
+}
+
+/// Adds speed in `wish_direction` until the speed along it reaches `max_speed`.
+///
+/// How fast that happens still scales with the uncapped `wish_speed`, in the air this lets
+/// turning against the current velocity keep adding a little speed sideways every tick.
 fn accelerate(
     wish_direction: Vec3,
     wish_speed: f32,
+    max_speed: f32,
     current_speed: f32,
     accel: f32,
     delta_seconds: f32,
 ) -> Vec3 {
-    let add_speed = wish_speed - current_speed;
+    let add_speed = wish_speed.min(max_speed) - current_speed;
 
     if add_speed <= 0.0 {
         return Vec3::ZERO;
@@ -222,3 +765,278 @@ fn accelerate(
 
     wish_direction * accel_speed
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    const TEST_DELTA_SECONDS: f32 = 1.0 / 64.0;
+
+    /// A floor with a box of `obstacle_height` on it, starting one meter ahead of the origin.
+    fn setup_app(obstacle_height: f32) -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            PhysicsPlugins::default(),
+        ))
+        // avian's collider constructors want these even though we never use them
+        .init_asset::<Mesh>()
+        .init_resource::<bevy::scene::SceneSpawner>();
+
+        app.world_mut().spawn((
+            RigidBody::Static,
+            Collider::cuboid(20.0, 1.0, 20.0),
+            TransformBundle::from_transform(Transform::from_xyz(0.0, -0.5, 0.0)),
+        ));
+        app.world_mut().spawn((
+            RigidBody::Static,
+            Collider::cuboid(8.0, obstacle_height, 4.0),
+            TransformBundle::from_transform(Transform::from_xyz(5.0, obstacle_height / 2.0, 0.0)),
+        ));
+        app.world_mut().spawn(spawn_bundle());
+
+        // let the spatial query pipeline pick up the colliders
+        app.update();
+        app
+    }
+
+    fn spawn_bundle() -> (
+        CharacterState,
+        CharacterConstants,
+        CharacterCollider,
+        Transform,
+    ) {
+        (
+            CharacterState {
+                owner_client_id: 0,
+                velocity: Vec3::ZERO,
+                visuals_offset: Vec3::ZERO,
+                is_grounded: false,
+                is_crouching: false,
+            },
+            CharacterConstants::default(),
+            CharacterCollider::new(CHARACTER_RADIUS, CHARACTER_HEIGHT),
+            Transform::from_xyz(0.0, CHARACTER_HEIGHT / 2.0 + 0.05, 0.0),
+        )
+    }
+
+    /// Moves the character for `ticks` ticks, `input` picks what is pressed on each of them.
+    fn simulate(
+        app: &mut App,
+        ticks: u32,
+        obstacles: &[CharacterObstacle],
+        input: impl Fn(&CharacterState) -> PlayerInput + Send + Sync + 'static,
+    ) {
+        let obstacles = obstacles.to_vec();
+        app.world_mut().run_system_once(
+            move |spatial_query: SpatialQuery,
+                  mut characters: Query<(
+                &mut CharacterState,
+                &mut CharacterCollider,
+                &mut Transform,
+                &CharacterConstants,
+            )>| {
+                let (mut state, mut collider, mut transform, constants) = characters.single_mut();
+                for _ in 0..ticks {
+                    move_character(
+                        &input(&state),
+                        &spatial_query,
+                        &obstacles,
+                        &mut state,
+                        &mut collider,
+                        &mut transform,
+                        constants,
+                        TEST_DELTA_SECONDS,
+                    );
+                }
+            },
+        );
+    }
+
+    #[test]
+    fn armor_absorbs_part_of_the_damage_until_it_runs_out() {
+        let mut health = CharacterHealth {
+            health: 100,
+            armor: 50,
+        };
+        assert!(!health.take_damage(30));
+        assert_eq!((health.health, health.armor), (90, 30));
+
+        // only 30 armor left to absorb 66 of this
+        assert!(!health.take_damage(100));
+        assert_eq!((health.health, health.armor), (20, 0));
+
+        assert!(health.take_damage(40));
+        assert_eq!(health.health, 0);
+        // the dead can't be killed again
+        assert!(!health.take_damage(40));
+    }
+
+    /// Walks towards +X for `ticks` ticks and returns where the character ended up.
+    fn walk_forward(app: &mut App, ticks: u32) -> Vec3 {
+        simulate(app, ticks, &[], |_| PlayerInput {
+            move_forward: true,
+            yaw: -std::f32::consts::FRAC_PI_2,
+            ..default()
+        });
+        app.world_mut()
+            .query_filtered::<&Transform, With<CharacterState>>()
+            .single(app.world())
+            .translation
+    }
+
+    fn character_mut(app: &mut App) -> Mut<'_, CharacterState> {
+        app.world_mut()
+            .query::<&mut CharacterState>()
+            .single_mut(app.world_mut())
+    }
+
+    fn use_quake_movement(app: &mut App) {
+        *app.world_mut()
+            .query::<&mut CharacterConstants>()
+            .single_mut(app.world_mut()) = CharacterConstants {
+            tag: "quake".to_string(),
+            move_speed: 7.0,
+            crouch_speed: 3.0,
+            ground_accel: 10.0,
+            air_accel: 10.0,
+            air_speed_cap: 0.7,
+            friction: 4.0,
+            stop_speed: 2.2,
+            ..default()
+        };
+    }
+
+    fn horizontal_speed(velocity: Vec3) -> f32 {
+        Vec3::new(velocity.x, 0.0, velocity.z).length()
+    }
+
+    #[test]
+    fn climbs_a_small_step() {
+        let mut app = setup_app(0.2);
+        let position = walk_forward(&mut app, 96);
+
+        assert!(position.x > 1.5, "didn't get onto the step: {position}");
+        let feet_height = position.y - CHARACTER_HEIGHT / 2.0;
+        assert!(
+            (feet_height - 0.2).abs() < 0.05,
+            "not standing on the step: {position}"
+        );
+    }
+
+    #[test]
+    fn does_not_climb_a_wall() {
+        let mut app = setup_app(1.0);
+        let position = walk_forward(&mut app, 128);
+
+        assert!(position.x < 1.0, "walked into the wall: {position}");
+        let feet_height = position.y - CHARACTER_HEIGHT / 2.0;
+        assert!(feet_height < 0.05, "climbed the wall: {position}");
+    }
+
+    #[test]
+    fn jumping_on_landing_skips_friction() {
+        let mut app = setup_app(0.2);
+        use_quake_movement(&mut app);
+        // settle onto the floor
+        simulate(&mut app, 16, &[], |_| PlayerInput::default());
+
+        for jump in [true, false] {
+            let mut state = character_mut(&mut app);
+            assert!(state.is_grounded);
+            state.velocity = Vec3::new(-7.0, 0.0, 0.0);
+            simulate(&mut app, 1, &[], move |_| PlayerInput { jump, ..default() });
+
+            let mut state = character_mut(&mut app);
+            let speed = horizontal_speed(state.velocity);
+            if jump {
+                assert!((speed - 7.0).abs() < 0.001, "lost speed jumping: {speed}");
+            } else {
+                assert!(speed < 6.9, "no friction on the ground: {speed}");
+            }
+            state.velocity = Vec3::ZERO;
+            simulate(&mut app, 128, &[], |_| PlayerInput::default());
+        }
+    }
+
+    #[test]
+    fn air_strafing_gains_speed() {
+        let mut app = setup_app(0.2);
+        use_quake_movement(&mut app);
+        character_mut(&mut app).velocity = Vec3::new(0.0, 0.0, -7.0);
+        app.world_mut()
+            .query_filtered::<&mut Transform, With<CharacterState>>()
+            .single_mut(app.world_mut())
+            .translation
+            .y = 10.0;
+
+        // strafe while turning to keep facing the way we're flying
+        simulate(&mut app, 64, &[], |state| PlayerInput {
+            move_right: true,
+            yaw: f32::atan2(-state.velocity.x, -state.velocity.z),
+            ..default()
+        });
+
+        let state = character_mut(&mut app);
+        assert!(!state.is_grounded);
+        let speed = horizontal_speed(state.velocity);
+        assert!(speed > 8.0, "didn't gain speed: {speed}");
+    }
+
+    #[test]
+    fn does_not_walk_through_other_characters() {
+        let mut app = setup_app(0.2);
+        let other = CharacterObstacle {
+            client_id: 1,
+            position: Vec3::new(-1.5, CHARACTER_HEIGHT / 2.0, 0.0),
+            radius: CHARACTER_RADIUS,
+            height: CHARACTER_HEIGHT,
+        };
+        // away from the step, towards -X
+        simulate(&mut app, 64, std::slice::from_ref(&other), |_| {
+            PlayerInput {
+                move_backward: true,
+                yaw: -std::f32::consts::FRAC_PI_2,
+                ..default()
+            }
+        });
+
+        let position = app
+            .world_mut()
+            .query_filtered::<&Transform, With<CharacterState>>()
+            .single(app.world())
+            .translation;
+        let gap = position.x - other.position.x;
+        assert!(
+            (gap - CHARACTER_RADIUS * 2.0).abs() < 0.01,
+            "not stopped by the other character: {position}"
+        );
+    }
+
+    #[test]
+    fn overlapping_characters_separate() {
+        let mut app = setup_app(0.2);
+        // settle onto the floor
+        simulate(&mut app, 16, &[], |_| PlayerInput::default());
+        let other = CharacterObstacle {
+            client_id: 1,
+            position: Vec3::new(0.0, CHARACTER_HEIGHT / 2.0, 0.0),
+            radius: CHARACTER_RADIUS,
+            height: CHARACTER_HEIGHT,
+        };
+        simulate(&mut app, 1, &[other], |_| PlayerInput::default());
+
+        // on the same spot with a lower client id we go towards +X, by half of the overlap
+        let position = app
+            .world_mut()
+            .query_filtered::<&Transform, With<CharacterState>>()
+            .single(app.world())
+            .translation;
+        assert!(
+            (position.x - CHARACTER_RADIUS).abs() < 0.01,
+            "didn't move out: {position}"
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use bevy_quinnet::shared::ClientId;
+use serde::{Deserialize, Serialize};
+
+/// Longer messages are cut off, in characters.
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 128;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ChatSender {
+    Player {
+        client_id: ClientId,
+        username: String,
+        team: Option<u8>,
+    },
+    /// Announcements like players joining or the match starting.
+    Server,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatMessage {
+    pub sender: ChatSender,
+    pub text: String,
+    /// Only the sender's team got this message.
+    pub team_only: bool,
+}
+
+/// Strips control characters and surrounding whitespace and caps the length. Returns `None` if
+/// nothing is left to send.
+pub fn sanitize_chat_message(text: &str) -> Option<String> {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+        .trim()
+        .chars()
+        .take(MAX_CHAT_MESSAGE_LENGTH)
+        .collect();
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_trimmed_and_stripped_of_control_characters() {
+        assert_eq!(
+            sanitize_chat_message("  gg\u{7} wp\n "),
+            Some("gg wp".to_string())
+        );
+        assert_eq!(sanitize_chat_message(" \t\u{1b} "), None);
+    }
+
+    #[test]
+    fn long_messages_are_cut_off() {
+        let text = sanitize_chat_message(&"é".repeat(MAX_CHAT_MESSAGE_LENGTH + 10)).unwrap();
+        assert_eq!(text.chars().count(), MAX_CHAT_MESSAGE_LENGTH);
+    }
+}
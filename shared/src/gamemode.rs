@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use bevy_quinnet::shared::ClientId;
+use serde::{Deserialize, Serialize};
+
+pub const TEAM_NAMES: [&str; 2] = ["Red", "Blue"];
+pub const TEAM_COLORS: [[f32; 3]; 2] = [[0.9, 0.2, 0.2], [0.2, 0.3, 0.9]];
+
+pub fn team_name(team: u8) -> &'static str {
+    TEAM_NAMES.get(team as usize).copied().unwrap_or("Unknown")
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GameModeKind {
+    #[default]
+    Deathmatch,
+    TeamDeathmatch,
+    CaptureTheFlag,
+}
+
+impl GameModeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameModeKind::Deathmatch => "Deathmatch",
+            GameModeKind::TeamDeathmatch => "Team Deathmatch",
+            GameModeKind::CaptureTheFlag => "Capture the Flag",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MatchPhase {
+    /// Waiting for enough players, then counting down to the start. Nothing is scored.
+    #[default]
+    Warmup,
+    Live,
+    /// The match is over and the winner is shown, then it goes back to warmup.
+    Intermission,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MatchWinner {
+    Player(ClientId),
+    Team(u8),
+    Draw,
+}
+
+/// A player's row on the scoreboard.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PlayerScore {
+    pub client_id: ClientId,
+    pub username: String,
+    pub team: Option<u8>,
+    pub score: i32,
+    pub kills: u32,
+    pub deaths: u32,
+    /// Round trip time to the server, refreshed every few seconds.
+    pub ping_ms: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FlagState {
+    pub team: u8,
+    /// Where the flag sits when it's not taken, it's captured by bringing the enemy flag here.
+    pub home: Vec3,
+    /// Where the flag was dropped, or `home`. Follows the carrier while carried.
+    pub position: Vec3,
+    pub carrier: Option<ClientId>,
+}
+
+impl FlagState {
+    pub fn new(team: u8, home: Vec3) -> Self {
+        FlagState {
+            team,
+            home,
+            position: home,
+            carrier: None,
+        }
+    }
+
+    pub fn is_home(&self) -> bool {
+        self.carrier.is_none() && self.position == self.home
+    }
+
+    pub fn return_home(&mut self) {
+        self.position = self.home;
+        self.carrier = None;
+    }
+}
+
+/// The state of the match, it's owned by the server and sent to clients whenever it changes.
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct MatchState {
+    pub mode: GameModeKind,
+    pub phase: MatchPhase,
+    /// The server tick the current phase ends on, `None` while waiting for players.
+    pub phase_end_tick: Option<u32>,
+    pub score_limit: u32,
+    pub players: Vec<PlayerScore>,
+    /// Indexed by team, empty without teams.
+    pub team_scores: Vec<i32>,
+    pub flags: Vec<FlagState>,
+    pub winner: Option<MatchWinner>,
+}
+
+impl MatchState {
+    pub fn player(&self, client_id: ClientId) -> Option<&PlayerScore> {
+        self.players
+            .iter()
+            .find(|player| player.client_id == client_id)
+    }
+
+    pub fn player_mut(&mut self, client_id: ClientId) -> Option<&mut PlayerScore> {
+        self.players
+            .iter_mut()
+            .find(|player| player.client_id == client_id)
+    }
+
+    /// The player's username, or a placeholder if they already left.
+    pub fn username(&self, client_id: ClientId) -> String {
+        self.player(client_id)
+            .map(|player| player.username.clone())
+            .unwrap_or_else(|| format!("Player {}", client_id))
+    }
+
+    pub fn team_of(&self, client_id: ClientId) -> Option<u8> {
+        self.player(client_id).and_then(|player| player.team)
+    }
+
+    pub fn add_team_score(&mut self, team: u8, amount: i32) {
+        if let Some(score) = self.team_scores.get_mut(team as usize) {
+            *score += amount;
+        }
+    }
+
+    /// Seconds left in the current phase, as seen from `tick`.
+    pub fn seconds_remaining(&self, tick: u32, tickrate: u32) -> Option<f32> {
+        self.phase_end_tick
+            .map(|end_tick| end_tick.saturating_sub(tick) as f32 / tickrate as f32)
+    }
+
+    /// Zeroes every score for a new match.
+    pub fn reset_scores(&mut self, team_count: u8) {
+        for player in &mut self.players {
+            player.score = 0;
+            player.kills = 0;
+            player.deaths = 0;
+        }
+        self.team_scores = vec![0; team_count as usize];
+        self.flags.clear();
+        self.winner = None;
+    }
+}
@@ -1,9 +1,17 @@
 pub mod character;
+pub mod chat;
+pub mod gamemode;
+pub mod map;
+pub mod pickups;
+pub mod projectiles;
 pub mod protocol;
 pub mod resources;
 pub mod utils;
 pub mod weapons;
 
+/// Simulation ticks per second, the client and the server have to agree on this.
+pub const TICKRATE: u32 = 64;
+
 pub use avian3d;
 pub use bevy;
 pub use bevy_common_assets;
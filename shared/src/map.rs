@@ -0,0 +1,301 @@
+use crate::character::{CharacterCollider, CharacterObstacle};
+use crate::utils::Fnv1a;
+use avian3d::prelude::*;
+use bevy::{
+    asset::io::{AssetSource, AssetSourceBuilder},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+/// Spawn points closer than this to an enemy are only used when there's nothing better.
+pub const SAFE_SPAWN_DISTANCE: f32 = 6.0;
+
+/// Asset source for files both the client and the server ship with, it points at `shared/assets`.
+pub const SHARED_ASSET_SOURCE: &str = "shared";
+
+pub fn shared_asset_source() -> AssetSourceBuilder {
+    AssetSource::build().with_reader(AssetSource::get_default_reader(
+        "../shared/assets".to_string(),
+    ))
+}
+
+/// Where the map called `name` is loaded from.
+pub fn map_path(name: &str) -> String {
+    format!("{}://maps/{}.map.ron", SHARED_ASSET_SOURCE, name)
+}
+
+/// A level, loaded from `*.map.ron` files by the client and the server alike.
+#[derive(Serialize, Deserialize, Asset, TypePath, Clone, Debug)]
+pub struct MapConfig {
+    pub name: String,
+    pub geometry: Vec<MapGeometry>,
+    pub spawn_points: Vec<SpawnPoint>,
+    pub pickups: Vec<MapPickup>,
+    /// Where each team's flag stands in capture the flag.
+    #[serde(default)]
+    pub flag_bases: Vec<FlagBase>,
+}
+
+impl MapConfig {
+    /// Hashes everything that affects collisions, if this differs between the client and the
+    /// server prediction can't work.
+    pub fn collision_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        for geometry in &self.geometry {
+            match geometry.shape {
+                MapShape::Cuboid { size } => {
+                    hasher.write_f32(0.0);
+                    for value in size.to_array() {
+                        hasher.write_f32(value);
+                    }
+                }
+                MapShape::Cylinder { radius, height } => {
+                    hasher.write_f32(1.0);
+                    hasher.write_f32(radius);
+                    hasher.write_f32(height);
+                }
+            }
+            for value in geometry.position.to_array() {
+                hasher.write_f32(value);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Picks where a character of `team` should spawn.
+    ///
+    /// Points inside another character are skipped. Of the rest, points at least
+    /// `SAFE_SPAWN_DISTANCE` from every enemy are taken in turn using `rotation`, so players
+    /// don't always appear in the same place. If no point is safe the one furthest from the
+    /// enemies is used.
+    pub fn select_spawn_point(
+        &self,
+        team: Option<u8>,
+        collider: &CharacterCollider,
+        characters: &[CharacterObstacle],
+        enemy_positions: &[Vec3],
+        rotation: usize,
+    ) -> Option<&SpawnPoint> {
+        let distance_to_enemies = |spawn_point: &SpawnPoint| {
+            enemy_positions
+                .iter()
+                .map(|position| position.distance(spawn_point.position))
+                .fold(f32::INFINITY, f32::min)
+        };
+
+        let team_points: Vec<&SpawnPoint> = self
+            .spawn_points
+            .iter()
+            .filter(|spawn_point| spawn_point.is_for_team(team))
+            .collect();
+        let free_points: Vec<&SpawnPoint> = team_points
+            .iter()
+            .copied()
+            .filter(|spawn_point| {
+                characters.iter().all(|character| {
+                    !collider.overlaps(
+                        spawn_point.position,
+                        character.position,
+                        character.radius,
+                        character.height / 2.0,
+                    )
+                })
+            })
+            .collect();
+        // spawning inside someone beats not spawning at all
+        let candidates = if free_points.is_empty() {
+            team_points
+        } else {
+            free_points
+        };
+
+        let safe_points: Vec<&SpawnPoint> = candidates
+            .iter()
+            .copied()
+            .filter(|spawn_point| distance_to_enemies(spawn_point) >= SAFE_SPAWN_DISTANCE)
+            .collect();
+        if !safe_points.is_empty() {
+            return Some(safe_points[rotation % safe_points.len()]);
+        }
+
+        candidates
+            .into_iter()
+            .max_by(|a, b| distance_to_enemies(a).total_cmp(&distance_to_enemies(b)))
+    }
+}
+
+/// A static piece of the level, it's both collided with and drawn.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapGeometry {
+    pub shape: MapShape,
+    pub position: Vec3,
+    /// Only used by the client to draw it.
+    pub color: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum MapShape {
+    Cuboid { size: Vec3 },
+    Cylinder { radius: f32, height: f32 },
+}
+
+impl MapShape {
+    pub fn collider(&self) -> Collider {
+        match *self {
+            MapShape::Cuboid { size } => Collider::cuboid(size.x, size.y, size.z),
+            MapShape::Cylinder { radius, height } => Collider::cylinder(radius, height),
+        }
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        match *self {
+            MapShape::Cuboid { size } => Mesh::from(Cuboid {
+                half_size: size / 2.0,
+            }),
+            MapShape::Cylinder { radius, height } => Mesh::from(Cylinder {
+                radius,
+                half_height: height / 2.0,
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SpawnPoint {
+    pub position: Vec3,
+    pub yaw: f32,
+    /// Only characters on this team spawn here, points without a team are for everyone.
+    #[serde(default)]
+    pub team: Option<u8>,
+}
+
+impl SpawnPoint {
+    /// Points without a team are used by everyone, when there are no teams every point is used.
+    pub fn is_for_team(&self, team: Option<u8>) -> bool {
+        team.is_none() || self.team.is_none() || self.team == team
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FlagBase {
+    pub team: u8,
+    pub position: Vec3,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapPickup {
+    pub id: u32,
+    pub weapon_config_tag: String,
+    pub ammo: u32,
+    pub respawn_seconds: f32,
+    pub position: Vec3,
+}
+
+/// The map being played.
+///
+/// The server fills in `collision_hash` once the map is loaded, clients get it from the server
+/// and check their copy of the map against it.
+#[derive(Resource)]
+pub struct CurrentMap {
+    pub name: String,
+    pub handle: Handle<MapConfig>,
+    pub collision_hash: Option<u64>,
+    pub is_spawned: bool,
+}
+
+impl CurrentMap {
+    pub fn load(asset_server: &AssetServer, name: &str, collision_hash: Option<u64>) -> Self {
+        CurrentMap {
+            name: name.to_string(),
+            handle: asset_server.load(map_path(name)),
+            collision_hash,
+            is_spawned: false,
+        }
+    }
+}
+
+/// Run condition for systems that need the level to be there.
+pub fn map_spawned(current_map: Option<Res<CurrentMap>>) -> bool {
+    current_map.is_some_and(|current_map| current_map.is_spawned)
+}
+
+/// Marks everything spawned from the map.
+#[derive(Component)]
+pub struct MapEntity;
+
+pub fn spawn_map_geometry(commands: &mut Commands, geometry: &MapGeometry) -> Entity {
+    commands
+        .spawn((
+            MapEntity,
+            SpatialBundle {
+                transform: Transform::from_translation(geometry.position),
+                ..default()
+            },
+            geometry.shape.collider(),
+            RigidBody::Static,
+        ))
+        .id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_with_spawn_points(spawn_points: &[(Vec3, Option<u8>)]) -> MapConfig {
+        MapConfig {
+            name: "test".to_string(),
+            geometry: Vec::new(),
+            spawn_points: spawn_points
+                .iter()
+                .map(|&(position, team)| SpawnPoint {
+                    position,
+                    yaw: 0.0,
+                    team,
+                })
+                .collect(),
+            pickups: Vec::new(),
+            flag_bases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn spawns_away_from_enemies_and_other_characters() {
+        let near = Vec3::new(0.0, 0.5, 0.0);
+        let middle = Vec3::new(4.0, 0.5, 0.0);
+        let far = Vec3::new(10.0, 0.5, 0.0);
+        let map = map_with_spawn_points(&[(near, None), (middle, None), (far, None)]);
+        let collider = CharacterCollider::new(0.5, 1.0);
+
+        // the enemy stands on the closest point and the far one is taken, the middle one is
+        // too close to be safe but it's the best there is
+        let enemy = CharacterObstacle::new(1, near, &collider);
+        let friend = CharacterObstacle::new(2, far, &collider);
+        for rotation in 0..3 {
+            let spawn_point = map
+                .select_spawn_point(
+                    None,
+                    &collider,
+                    &[enemy.clone(), friend.clone()],
+                    &[enemy.position],
+                    rotation,
+                )
+                .unwrap();
+            assert_eq!(spawn_point.position, middle);
+        }
+    }
+
+    #[test]
+    fn spawns_at_own_team_points() {
+        let red = Vec3::new(-5.0, 0.5, 0.0);
+        let blue = Vec3::new(5.0, 0.5, 0.0);
+        let map = map_with_spawn_points(&[(red, Some(0)), (blue, Some(1))]);
+        let collider = CharacterCollider::new(0.5, 1.0);
+
+        for rotation in 0..2 {
+            let spawn_point = map
+                .select_spawn_point(Some(1), &collider, &[], &[], rotation)
+                .unwrap();
+            assert_eq!(spawn_point.position, blue);
+        }
+    }
+}
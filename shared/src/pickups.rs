@@ -0,0 +1,67 @@
+use crate::{character::CharacterCollider, protocol::PickupSnapshot};
+use bevy::prelude::*;
+
+pub const PICKUP_RADIUS: f32 = 0.4;
+
+#[derive(Component)]
+pub struct WeaponPickup {
+    pub id: u32,
+    pub weapon_config_tag: String,
+    pub ammo: u32,
+    pub respawn_seconds: f32,
+    pub respawn_remaining: f32,
+}
+
+impl WeaponPickup {
+    pub fn is_available(&self) -> bool {
+        self.respawn_remaining <= 0.0
+    }
+
+    pub fn overlaps(
+        &self,
+        pickup_position: Vec3,
+        character_position: Vec3,
+        character_collider: &CharacterCollider,
+    ) -> bool {
+        character_collider.overlaps(
+            character_position,
+            pickup_position,
+            PICKUP_RADIUS,
+            PICKUP_RADIUS,
+        )
+    }
+
+    pub fn apply_snapshot(&mut self, snapshot: &PickupSnapshot) {
+        // clients don't count down respawns, they just need to know if it's there
+        self.respawn_remaining = if snapshot.available {
+            0.0
+        } else {
+            self.respawn_seconds.max(1.0)
+        };
+    }
+}
+
+pub fn spawn_weapon_pickup(
+    commands: &mut Commands,
+    id: u32,
+    weapon_config_tag: &str,
+    ammo: u32,
+    respawn_seconds: f32,
+    position: Vec3,
+) -> Entity {
+    commands
+        .spawn((
+            WeaponPickup {
+                id,
+                weapon_config_tag: weapon_config_tag.to_string(),
+                ammo,
+                respawn_seconds,
+                respawn_remaining: 0.0,
+            },
+            SpatialBundle {
+                transform: Transform::from_translation(position),
+                ..default()
+            },
+        ))
+        .id()
+}
@@ -0,0 +1,132 @@
+use crate::{character::CharacterObstacle, protocol::ProjectileSnapshot, weapons::WeaponConfig};
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_quinnet::shared::ClientId;
+
+// projectiles that never hit anything get cleaned up after this long
+const PROJECTILE_LIFETIME: f32 = 10.0;
+
+#[derive(Component)]
+pub struct Projectile {
+    pub owner_client_id: ClientId,
+    pub weapon_config_tag: String,
+    pub fired_input_id: u32,
+    pub velocity: Vec3,
+    pub gravity: f32,
+    pub lifetime_remaining: f32,
+}
+
+impl Projectile {
+    pub fn is_expired(&self) -> bool {
+        self.lifetime_remaining <= 0.0
+    }
+
+    pub fn apply_snapshot(
+        &mut self,
+        snapshot: &ProjectileSnapshot,
+        existing_transform: &mut Transform,
+    ) {
+        self.velocity = snapshot.velocity;
+        existing_transform.translation = snapshot.position;
+    }
+}
+
+pub enum ProjectileImpact {
+    World { position: Vec3 },
+    Character { client_id: ClientId, position: Vec3 },
+}
+
+impl ProjectileImpact {
+    pub fn position(&self) -> Vec3 {
+        match self {
+            ProjectileImpact::World { position } => *position,
+            ProjectileImpact::Character { position, .. } => *position,
+        }
+    }
+}
+
+pub fn spawn_projectile(
+    commands: &mut Commands,
+    owner_client_id: ClientId,
+    weapon_config: &WeaponConfig,
+    fired_input_id: u32,
+    origin: Vec3,
+    direction: Vec3,
+) -> Entity {
+    commands
+        .spawn((
+            Projectile {
+                owner_client_id,
+                weapon_config_tag: weapon_config.tag.clone(),
+                fired_input_id,
+                velocity: direction.normalize_or_zero() * weapon_config.projectile_speed,
+                gravity: weapon_config.projectile_gravity,
+                lifetime_remaining: PROJECTILE_LIFETIME,
+            },
+            SpatialBundle {
+                transform: Transform::from_translation(origin),
+                ..default()
+            },
+        ))
+        .id()
+}
+
+/// Moves a projectile one step, returning where it hit if it did.
+///
+/// `characters` are every character the projectile can hit, the owner is skipped.
+pub fn move_projectile(
+    spatial_query: &SpatialQuery,
+    projectile: &mut Projectile,
+    transform: &mut Transform,
+    characters: &[CharacterObstacle],
+    delta_seconds: f32,
+) -> Option<ProjectileImpact> {
+    projectile.velocity.y -= projectile.gravity * delta_seconds;
+    projectile.lifetime_remaining -= delta_seconds;
+
+    let move_delta = projectile.velocity * delta_seconds;
+    let Ok(direction) = Dir3::new(move_delta) else {
+        return None;
+    };
+    let distance = move_delta.length();
+
+    let mut impact = spatial_query
+        .cast_ray(
+            transform.translation,
+            direction,
+            distance,
+            true,
+            SpatialQueryFilter::default(),
+        )
+        .map(|hit| ProjectileImpact::World {
+            position: transform.translation + direction * hit.time_of_impact,
+        });
+    let mut closest_distance = impact
+        .as_ref()
+        .map(|impact| impact.position().distance(transform.translation))
+        .unwrap_or(distance);
+
+    for character in characters {
+        if character.client_id == projectile.owner_client_id {
+            continue;
+        }
+        let character_collider = Collider::cylinder(character.radius, character.height);
+        if let Some((time_of_impact, _)) = character_collider.cast_ray(
+            character.position,
+            Quat::IDENTITY,
+            transform.translation,
+            direction.as_vec3(),
+            closest_distance,
+            true,
+        ) {
+            closest_distance = time_of_impact;
+            impact = Some(ProjectileImpact::Character {
+                client_id: character.client_id,
+                position: transform.translation + direction * time_of_impact,
+            });
+        }
+    }
+
+    transform.translation += direction * closest_distance;
+    impact
+}
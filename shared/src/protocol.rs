@@ -5,64 +5,277 @@ use bevy_quinnet::shared::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::weapons::WeaponConfig;
+use crate::character::CharacterConstants;
+use crate::chat::ChatMessage;
+use crate::gamemode::{GameModeKind, MatchState};
+use crate::utils::Fnv1a;
+use crate::weapons::{ExplosionFalloff, FireMode, WeaponConfig, WeaponFiredHitscanEvent};
 
 mod impl_character_snapshot;
 mod impl_player_input;
 mod impl_tick_snapshot;
 
+/// Bumped whenever a message changes, clients and servers only talk to the same version.
+pub const PROTOCOL_VERSION: u32 = 7;
+
+// usernames are cut off at this many characters
+pub const MAX_USERNAME_LENGTH: usize = 24;
+
+/// How many of the latest unacked inputs every input packet repeats, so that losing a few
+/// packets in a row doesn't lose any input.
+pub const MAX_REDUNDANT_INPUTS: usize = 8;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientMessage {
-    Connect { username: String },
+    Connect {
+        username: String,
+        protocol_version: u32,
+    },
     Disconnect {},
-    ChatMessage(String),
-    PlayerInput(PlayerInput),
+    ChatMessage {
+        text: String,
+        team_only: bool,
+    },
+    /// The latest unacked inputs, oldest first, the last one is the newest input.
+    PlayerInputs(Vec<PlayerInput>),
+    /// Answered right away with a `Pong`, `client_seconds` is our clock when we sent it.
+    Ping {
+        client_seconds: f64,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
+    /// Answers a `Ping` with the tick the server was on when it got it.
+    Pong {
+        client_seconds: f64,
+        server_tick: u32,
+    },
+    /// Answers `Connect` when the client was let in.
+    Welcome {
+        client_id: ClientId,
+        tickrate: u32,
+        /// See [`content_hash`], the client checks its own once it has everything.
+        content_hash: u64,
+        settings: ServerSettings,
+    },
+    /// Answers `Connect` when the client wasn't let in, the server disconnects it right after.
+    Rejected(RejectReason),
+    LoadMap {
+        name: String,
+        collision_hash: u64,
+    },
     WeaponConfig(WeaponConfig),
+    MovementProfile(CharacterConstants),
     TickSnapshot(TickSnapshot),
+    WeaponFiredHitscan(WeaponFiredHitscanEvent),
+    MatchState(MatchState),
+    Chat(ChatMessage),
+    Kill {
+        killer_client_id: ClientId,
+        victim_client_id: ClientId,
+        weapon_config_tag: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerSettings {
+    pub server_name: String,
+    pub max_players: usize,
+    pub game_mode: GameModeKind,
+    pub map: String,
+    /// Every weapon config the server will send.
+    pub weapon_config_tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RejectReason {
+    VersionMismatch {
+        server_version: u32,
+        client_version: u32,
+    },
+    ServerFull {
+        max_players: usize,
+    },
+    Banned,
+    BadUsername(String),
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::VersionMismatch {
+                server_version,
+                client_version,
+            } => write!(
+                f,
+                "protocol version mismatch, the server runs {} and the client {}",
+                server_version, client_version
+            ),
+            RejectReason::ServerFull { max_players } => {
+                write!(f, "the server is full ({} players)", max_players)
+            }
+            RejectReason::Banned => write!(f, "banned from this server"),
+            RejectReason::BadUsername(reason) => write!(f, "bad username, {}", reason),
+        }
+    }
+}
+
+/// Checks a username is something others can read, returns it trimmed.
+pub fn validate_username(username: &str) -> Result<String, String> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err("it's empty".to_string());
+    }
+    if username.chars().count() > MAX_USERNAME_LENGTH {
+        return Err(format!("longer than {} characters", MAX_USERNAME_LENGTH));
+    }
+    if username.chars().any(char::is_control) {
+        return Err("it has control characters".to_string());
+    }
+    Ok(username.to_string())
+}
+
+/// Hashes the content both sides have to agree on: the map's collisions, which the client loads
+/// from its own files, and the weapon configs as each build reads them.
+pub fn content_hash(collision_hash: u64, weapon_configs: &[&WeaponConfig]) -> u64 {
+    let mut weapon_configs = weapon_configs.to_vec();
+    weapon_configs.sort_by(|a, b| a.tag.cmp(&b.tag));
+
+    let mut hasher = Fnv1a::default();
+    hasher.write_u64(collision_hash);
+    for weapon_config in weapon_configs {
+        for text in [&weapon_config.tag, &weapon_config.name] {
+            hasher.write_u64(text.len() as u64);
+            hasher.write(text.as_bytes());
+        }
+        hasher.write_u64(match weapon_config.fire_mode {
+            FireMode::Hitscan => 0,
+            FireMode::Projectile => 1,
+        });
+        for value in [
+            weapon_config.fire_rate_ms,
+            weapon_config.switch_time_ms,
+            weapon_config.damage,
+            weapon_config.magazine_size,
+            weapon_config.reload_time_ms,
+            weapon_config.max_ammo,
+        ] {
+            hasher.write_u64(value as u64);
+        }
+        for value in [
+            weapon_config.projectile_speed,
+            weapon_config.projectile_gravity,
+            weapon_config.explosion_radius,
+            weapon_config.self_damage_multiplier,
+            weapon_config.knockback_impulse,
+        ] {
+            hasher.write_f32(value);
+        }
+        hasher.write_u64(match weapon_config.explosion_falloff {
+            ExplosionFalloff::Constant => 0,
+            ExplosionFalloff::Linear => 1,
+            ExplosionFalloff::Quadratic => 2,
+        });
+    }
+    hasher.finish()
 }
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct PlayerInput {
     pub id: u32,
     pub server_tick: Option<u32>,
+    /// The server tick other characters were drawn at, shots are checked against them as they
+    /// were then.
+    pub interpolation_tick: Option<u32>,
     pub move_forward: bool,
     pub move_backward: bool,
     pub move_left: bool,
     pub move_right: bool,
-    pub move_up: bool,
-    pub move_down: bool,
+    pub jump: bool,
+    pub crouch: bool,
     pub yaw: f32,
     pub pitch: f32,
     pub fire: bool,
+    pub reload: bool,
+    pub switch_weapon: Option<u8>,
     pub final_position: Vec3,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct WeaponSnapshot {
+    pub weapon_config_tag: String,
+    pub magazine: u32,
+    pub reserve_ammo: u32,
+    pub next_fire_tick: u32,
+    pub reload_finish_tick: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct CharacterSnapshot {
     pub owner_client_id: ClientId,
     pub position: Option<Vec3>,
+    /// Which way the character faced when it spawned, only the owner's input turns it after that.
+    pub yaw: Option<f32>,
     pub velocity: Option<Vec3>,
+    pub is_crouching: Option<bool>,
+    pub health: Option<u32>,
+    pub armor: Option<u32>,
+    pub active_weapon: Option<u8>,
+    pub weapons: Option<Vec<WeaponSnapshot>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ProjectileSnapshot {
+    pub id: u64,
+    pub owner_client_id: ClientId,
+    pub weapon_config_tag: String,
+    pub fired_input_id: u32,
+    pub position: Vec3,
+    pub velocity: Vec3,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PickupSnapshot {
+    pub id: u32,
+    pub weapon_config_tag: String,
+    pub position: Vec3,
+    pub available: bool,
+}
+
+/// How the server's queue of our inputs is doing. Clients tick a little faster or slower to keep
+/// it at the target depth.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct InputBufferHealth {
+    pub depth: u32,
+    pub target_depth: u32,
+    /// Ticks the server had no input of ours to simulate, since we joined.
+    pub starved_ticks: u32,
+    /// Inputs the server dropped because we got too far ahead, since we joined.
+    pub overflowed_inputs: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct TickSnapshot {
     pub tick: u32,
+    /// The tick this snapshot was diffed against, `None` for a full snapshot.
+    pub baseline_tick: Option<u32>,
     pub acked_input_id: Option<u32>,
+    pub input_buffer: Option<InputBufferHealth>,
     pub characters: Vec<CharacterSnapshot>,
+    pub projectiles: Vec<ProjectileSnapshot>,
+    pub pickups: Vec<PickupSnapshot>,
 }
 
 #[repr(u8)]
 pub enum ClientChannels {
     Events,
     PlayerInputs,
+    Pings,
 }
-impl Into<ChannelId> for ClientChannels {
-    fn into(self) -> ChannelId {
-        self as ChannelId
+impl From<ClientChannels> for ChannelId {
+    fn from(channel: ClientChannels) -> Self {
+        channel as ChannelId
     }
 }
 impl ClientChannels {
@@ -70,6 +283,7 @@ impl ClientChannels {
         ChannelsConfiguration::from_types(vec![
             ChannelType::OrderedReliable,
             ChannelType::Unreliable,
+            ChannelType::Unreliable,
         ])
         .unwrap()
     }
@@ -80,10 +294,12 @@ pub enum ServerChannels {
     SnapshotDiff,
     SnapshotFull,
     ImportantData,
+    GameEvents,
+    Pings,
 }
-impl Into<ChannelId> for ServerChannels {
-    fn into(self) -> ChannelId {
-        self as ChannelId
+impl From<ServerChannels> for ChannelId {
+    fn from(channel: ServerChannels) -> Self {
+        channel as ChannelId
     }
 }
 impl ServerChannels {
@@ -92,6 +308,8 @@ impl ServerChannels {
             ChannelType::Unreliable,
             ChannelType::UnorderedReliable,
             ChannelType::UnorderedReliable,
+            ChannelType::OrderedReliable,
+            ChannelType::Unreliable,
         ])
         .unwrap()
     }
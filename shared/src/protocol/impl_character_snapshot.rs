@@ -9,11 +9,37 @@ impl CharacterSnapshot {
             } else {
                 None
             },
+            yaw: if self.yaw != old.yaw { self.yaw } else { None },
             velocity: if self.velocity != old.velocity {
                 self.velocity
             } else {
                 None
             },
+            is_crouching: if self.is_crouching != old.is_crouching {
+                self.is_crouching
+            } else {
+                None
+            },
+            health: if self.health != old.health {
+                self.health
+            } else {
+                None
+            },
+            armor: if self.armor != old.armor {
+                self.armor
+            } else {
+                None
+            },
+            active_weapon: if self.active_weapon != old.active_weapon {
+                self.active_weapon
+            } else {
+                None
+            },
+            weapons: if self.weapons != old.weapons {
+                self.weapons.clone()
+            } else {
+                None
+            },
         }
     }
 
@@ -21,7 +47,13 @@ impl CharacterSnapshot {
         CharacterSnapshot {
             owner_client_id: self.owner_client_id,
             position: self.position.or(old.position),
+            yaw: self.yaw.or(old.yaw),
             velocity: self.velocity.or(old.velocity),
+            is_crouching: self.is_crouching.or(old.is_crouching),
+            health: self.health.or(old.health),
+            armor: self.armor.or(old.armor),
+            active_weapon: self.active_weapon.or(old.active_weapon),
+            weapons: self.weapons.clone().or_else(|| old.weapons.clone()),
         }
     }
 }
@@ -19,15 +19,14 @@ impl PlayerInput {
         if self.move_right {
             wish_dir += rotation * Vec3::X;
         }
-        if self.move_up {
-            wish_dir += Vec3::Y;
-        }
-        if self.move_down {
-            wish_dir -= Vec3::Y;
-        }
         if wish_dir.length_squared() > 0.0 {
             wish_dir = wish_dir.normalize();
         }
         wish_dir
     }
+
+    pub fn compute_aim_dir(&self) -> Vec3 {
+        let rotation = Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0);
+        rotation * -Vec3::Z
+    }
 }
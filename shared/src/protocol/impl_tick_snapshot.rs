@@ -19,8 +19,118 @@ impl TickSnapshot {
 
         TickSnapshot {
             tick: self.tick,
+            baseline_tick: Some(old.tick),
             acked_input_id: self.acked_input_id,
+            input_buffer: self.input_buffer,
             characters,
+            // projectiles move every tick, there is nothing to gain from diffing them
+            projectiles: self.projectiles.clone(),
+            // pickups never go away, so only the ones that changed are sent
+            pickups: self
+                .pickups
+                .iter()
+                .filter(|pickup| !old.pickups.contains(pickup))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Turns a diff back into the full snapshot, `baseline` has to be the one it was diffed
+    /// against.
+    pub fn apply_diff(&self, baseline: &TickSnapshot) -> TickSnapshot {
+        let characters = self
+            .characters
+            .iter()
+            .map(|new_char| {
+                baseline
+                    .characters
+                    .iter()
+                    .find(|old_char| old_char.owner_client_id == new_char.owner_client_id)
+                    .map(|old_char| new_char.apply_diff(old_char))
+                    .unwrap_or_else(|| new_char.clone())
+            })
+            .collect();
+
+        let mut pickups = baseline.pickups.clone();
+        for new_pickup in &self.pickups {
+            if let Some(pickup) = pickups.iter_mut().find(|pickup| pickup.id == new_pickup.id) {
+                *pickup = new_pickup.clone();
+            } else {
+                pickups.push(new_pickup.clone());
+            }
+        }
+
+        TickSnapshot {
+            tick: self.tick,
+            baseline_tick: None,
+            acked_input_id: self.acked_input_id,
+            input_buffer: self.input_buffer,
+            characters,
+            projectiles: self.projectiles.clone(),
+            pickups,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn character(owner_client_id: ClientId, position: Vec3, health: u32) -> CharacterSnapshot {
+        CharacterSnapshot {
+            owner_client_id,
+            position: Some(position),
+            yaw: Some(0.0),
+            velocity: Some(Vec3::ZERO),
+            is_crouching: Some(false),
+            health: Some(health),
+            armor: Some(0),
+            active_weapon: Some(0),
+            weapons: Some(Vec::new()),
+        }
+    }
+
+    fn pickup(id: u32, available: bool) -> PickupSnapshot {
+        PickupSnapshot {
+            id,
+            weapon_config_tag: "rocket_launcher".to_string(),
+            position: Vec3::ZERO,
+            available,
+        }
+    }
+
+    #[test]
+    fn applying_a_diff_gives_back_the_full_snapshot() {
+        let baseline = TickSnapshot {
+            tick: 10,
+            characters: vec![character(1, Vec3::ZERO, 100), character(2, Vec3::X, 100)],
+            pickups: vec![pickup(0, true), pickup(1, true)],
+            ..default()
+        };
+        let snapshot = TickSnapshot {
+            tick: 14,
+            acked_input_id: Some(7),
+            // 1 moved, 2 took damage and 3 joined
+            characters: vec![
+                character(1, Vec3::Y, 100),
+                character(2, Vec3::X, 60),
+                character(3, Vec3::Z, 100),
+            ],
+            pickups: vec![pickup(0, true), pickup(1, false)],
+            ..default()
+        };
+
+        let diff = snapshot.diff(&baseline);
+        assert_eq!(diff.baseline_tick, Some(10));
+        assert_eq!(diff.characters[0].health, None);
+        assert_eq!(diff.characters[1].position, None);
+        assert_eq!(diff.pickups, vec![pickup(1, false)]);
+
+        let applied = diff.apply_diff(&baseline);
+        assert_eq!(applied.baseline_tick, None);
+        assert_eq!(applied.tick, snapshot.tick);
+        assert_eq!(applied.acked_input_id, snapshot.acked_input_id);
+        assert_eq!(applied.characters, snapshot.characters);
+        assert_eq!(applied.pickups, snapshot.pickups);
+    }
+}
@@ -1,7 +1,13 @@
+use crate::character::CharacterConstants;
 use crate::weapons::WeaponConfig;
 use bevy::{prelude::*, utils::HashMap};
 
 #[derive(Resource, Default)]
 pub struct DataAssetHandles {
     pub weapon_configs: HashMap<String, Handle<WeaponConfig>>,
+    pub character_constants: HashMap<String, Handle<CharacterConstants>>,
 }
+
+/// The movement profile characters use in the current match.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct MovementProfile(pub CharacterConstants);
@@ -1,25 +1,286 @@
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::resources::DataAssetHandles;
+use crate::{
+    protocol::{CharacterSnapshot, PlayerInput, WeaponSnapshot},
+    resources::DataAssetHandles,
+    TICKRATE,
+};
 
-#[derive(Component)]
+/// How far hitscan shots travel before they are considered a miss.
+pub const MAX_SHOT_DISTANCE: f32 = 1000.0;
+
+/// Converts a duration from a weapon config into simulation ticks, rounding up.
+pub fn ms_to_ticks(ms: u32) -> u32 {
+    (ms * TICKRATE).div_ceil(1000)
+}
+
+/// The state of a single weapon, timings are in input ticks.
+///
+/// An input tick is the id of the input being simulated, the client produces one per tick
+/// so they are the character's own clock. The server and the client's replay simulate
+/// an input on the same input tick, which keeps their results identical.
+#[derive(Clone, Debug)]
 pub struct WeaponState {
     pub weapon_config_tag: String,
-    pub next_fire_time: u64,
-    pub ammo: u32,
+    pub next_fire_tick: u32,
+    pub magazine: u32,
+    pub reserve_ammo: u32,
+    pub reload_finish_tick: Option<u32>,
+}
+
+/// What a weapon did with the trigger input it was given this tick.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WeaponAction {
+    None,
+    Fired,
+    DryFired,
 }
 
 impl WeaponState {
-    pub fn can_fire(&self, time: &Time<Fixed>) -> bool {
-        let current_time = time.elapsed().as_millis() as u64;
-        current_time >= self.next_fire_time
+    pub fn new(weapon_config: &WeaponConfig, ammo: u32) -> Self {
+        let magazine = ammo.min(weapon_config.magazine_size);
+        WeaponState {
+            weapon_config_tag: weapon_config.tag.clone(),
+            next_fire_tick: 0,
+            magazine,
+            reserve_ammo: (ammo - magazine).min(weapon_config.max_ammo),
+            reload_finish_tick: None,
+        }
+    }
+
+    pub fn is_reloading(&self) -> bool {
+        self.reload_finish_tick.is_some()
+    }
+
+    /// Whether the trigger does anything, the cooldown has passed and we aren't reloading.
+    pub fn is_ready(&self, tick: u32) -> bool {
+        !self.is_reloading() && tick >= self.next_fire_tick
+    }
+
+    pub fn can_fire(&self, tick: u32) -> bool {
+        self.is_ready(tick) && self.magazine > 0
+    }
+
+    pub fn on_fire(&mut self, tick: u32, weapon_config: &WeaponConfig) {
+        self.next_fire_tick = tick + ms_to_ticks(weapon_config.fire_rate_ms);
+        self.magazine = self.magazine.saturating_sub(1);
+    }
+
+    /// Starts a reload if there is anything to reload. Returns true if it started.
+    pub fn start_reload(&mut self, tick: u32, weapon_config: &WeaponConfig) -> bool {
+        if self.is_reloading()
+            || self.reserve_ammo == 0
+            || self.magazine >= weapon_config.magazine_size
+        {
+            return false;
+        }
+        self.reload_finish_tick = Some(tick + ms_to_ticks(weapon_config.reload_time_ms));
+        true
+    }
+
+    pub fn cancel_reload(&mut self) {
+        self.reload_finish_tick = None;
+    }
+
+    fn finish_reload(&mut self, tick: u32, weapon_config: &WeaponConfig) {
+        if self
+            .reload_finish_tick
+            .is_some_and(|finish_tick| tick >= finish_tick)
+        {
+            let loaded = weapon_config
+                .magazine_size
+                .saturating_sub(self.magazine)
+                .min(self.reserve_ammo);
+            self.magazine += loaded;
+            self.reserve_ammo -= loaded;
+            self.reload_finish_tick = None;
+        }
+    }
+
+    /// Runs the weapon for one tick of input, this is shared by the server and the client's prediction.
+    pub fn update(
+        &mut self,
+        tick: u32,
+        weapon_config: &WeaponConfig,
+        fire: bool,
+        reload: bool,
+    ) -> WeaponAction {
+        self.finish_reload(tick, weapon_config);
+
+        if reload {
+            self.start_reload(tick, weapon_config);
+        }
+
+        if !fire || !self.is_ready(tick) {
+            return WeaponAction::None;
+        }
+
+        if self.magazine == 0 {
+            // click, and don't click again until the weapon would have fired
+            self.next_fire_tick = tick + ms_to_ticks(weapon_config.fire_rate_ms);
+            self.start_reload(tick, weapon_config);
+            return WeaponAction::DryFired;
+        }
+
+        self.on_fire(tick, weapon_config);
+        WeaponAction::Fired
+    }
+}
+
+#[derive(Component, Clone, Default)]
+pub struct WeaponInventory {
+    pub weapons: Vec<WeaponState>,
+    pub active_slot: usize,
+}
+
+impl WeaponInventory {
+    pub fn active_weapon(&self) -> Option<&WeaponState> {
+        self.weapons.get(self.active_slot)
+    }
+
+    pub fn active_weapon_mut(&mut self) -> Option<&mut WeaponState> {
+        self.weapons.get_mut(self.active_slot)
     }
 
-    pub fn on_fire(&mut self, time: &Time<Fixed>, weapon_config: &WeaponConfig) {
-        let current_time = time.elapsed().as_millis() as u64;
-        self.next_fire_time = current_time + weapon_config.fire_rate_ms as u64;
-        self.ammo -= 1;
+    pub fn slot_of(&self, weapon_config_tag: &str) -> Option<usize> {
+        self.weapons
+            .iter()
+            .position(|weapon| weapon.weapon_config_tag == weapon_config_tag)
+    }
+
+    /// Switches to the weapon in `slot`, it can't fire until its switch time has passed.
+    /// Returns false if there is no weapon in that slot or it's already active.
+    pub fn switch_to(
+        &mut self,
+        slot: usize,
+        tick: u32,
+        data_asset_handles: &DataAssetHandles,
+        weapon_configs: &Assets<WeaponConfig>,
+    ) -> bool {
+        if slot == self.active_slot {
+            return false;
+        }
+        let Some(weapon) = self.weapons.get_mut(slot) else {
+            return false;
+        };
+        let Some(weapon_config) = get_weapon_config(
+            data_asset_handles,
+            weapon_configs,
+            &weapon.weapon_config_tag,
+        ) else {
+            return false;
+        };
+
+        weapon.next_fire_tick = weapon
+            .next_fire_tick
+            .max(tick + ms_to_ticks(weapon_config.switch_time_ms));
+        if let Some(previous_weapon) = self.weapons.get_mut(self.active_slot) {
+            previous_weapon.cancel_reload();
+        }
+        self.active_slot = slot;
+        true
+    }
+
+    /// Switches and runs the active weapon for `input`, on the input tick `input.id`.
+    ///
+    /// This is all of the weapon logic an input drives, the server and the client's prediction
+    /// both go through here.
+    pub fn update<'a>(
+        &mut self,
+        input: &PlayerInput,
+        data_asset_handles: &DataAssetHandles,
+        weapon_configs: &'a Assets<WeaponConfig>,
+    ) -> Option<(WeaponAction, &'a WeaponConfig)> {
+        if let Some(slot) = input.switch_weapon {
+            self.switch_to(slot as usize, input.id, data_asset_handles, weapon_configs);
+        }
+
+        let weapon_state = self.active_weapon_mut()?;
+        let weapon_config = get_weapon_config(
+            data_asset_handles,
+            weapon_configs,
+            &weapon_state.weapon_config_tag,
+        )?;
+        let action = weapon_state.update(input.id, weapon_config, input.fire, input.reload);
+        Some((action, weapon_config))
+    }
+
+    /// Gives a weapon, or just its ammo if we already have it.
+    pub fn give(&mut self, weapon_config: &WeaponConfig, ammo: u32) {
+        if let Some(slot) = self.slot_of(&weapon_config.tag) {
+            let weapon = &mut self.weapons[slot];
+            weapon.reserve_ammo = (weapon.reserve_ammo + ammo).min(weapon_config.max_ammo);
+        } else {
+            self.weapons.push(WeaponState::new(weapon_config, ammo));
+        }
+    }
+
+    pub fn to_snapshot(&self) -> Vec<WeaponSnapshot> {
+        self.weapons
+            .iter()
+            .map(|weapon| WeaponSnapshot {
+                weapon_config_tag: weapon.weapon_config_tag.clone(),
+                magazine: weapon.magazine,
+                reserve_ammo: weapon.reserve_ammo,
+                next_fire_tick: weapon.next_fire_tick,
+                reload_finish_tick: weapon.reload_finish_tick,
+            })
+            .collect()
+    }
+
+    pub fn apply_snapshot(&mut self, snapshot: &CharacterSnapshot) {
+        if let Some(weapons) = &snapshot.weapons {
+            for weapon_snap in weapons {
+                let slot = self
+                    .slot_of(&weapon_snap.weapon_config_tag)
+                    .unwrap_or_else(|| {
+                        self.weapons.push(WeaponState {
+                            weapon_config_tag: weapon_snap.weapon_config_tag.clone(),
+                            next_fire_tick: 0,
+                            magazine: 0,
+                            reserve_ammo: 0,
+                            reload_finish_tick: None,
+                        });
+                        self.weapons.len() - 1
+                    });
+                let weapon = &mut self.weapons[slot];
+                weapon.magazine = weapon_snap.magazine;
+                weapon.reserve_ammo = weapon_snap.reserve_ammo;
+                weapon.next_fire_tick = weapon_snap.next_fire_tick;
+                weapon.reload_finish_tick = weapon_snap.reload_finish_tick;
+            }
+        }
+        if let Some(active_slot) = snapshot.active_weapon {
+            self.active_slot = active_slot as usize;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum FireMode {
+    Hitscan,
+    Projectile,
+}
+
+/// How explosion damage and knockback scale from the center to the edge of the radius.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum ExplosionFalloff {
+    Constant,
+    #[default]
+    Linear,
+    Quadratic,
+}
+
+impl ExplosionFalloff {
+    /// `fraction` is the distance from the center divided by the radius.
+    pub fn scale(&self, fraction: f32) -> f32 {
+        let remaining = (1.0 - fraction).clamp(0.0, 1.0);
+        match self {
+            ExplosionFalloff::Constant => 1.0,
+            ExplosionFalloff::Linear => remaining,
+            ExplosionFalloff::Quadratic => remaining * remaining,
+        }
     }
 }
 
@@ -27,13 +288,85 @@ impl WeaponState {
 pub struct WeaponConfig {
     pub tag: String,
     pub name: String,
+    pub fire_mode: FireMode,
     pub fire_rate_ms: u32,
+    pub switch_time_ms: u32,
     pub damage: u32,
+    pub magazine_size: u32,
+    pub reload_time_ms: u32,
+    /// The most ammo that can be carried on top of what's in the magazine.
+    pub max_ammo: u32,
+    #[serde(default)]
+    pub projectile_speed: f32,
+    #[serde(default)]
+    pub projectile_gravity: f32,
+    #[serde(default)]
+    pub explosion_radius: f32,
+    #[serde(default)]
+    pub explosion_falloff: ExplosionFalloff,
+    #[serde(default)]
+    pub self_damage_multiplier: f32,
+    #[serde(default)]
+    pub knockback_impulse: f32,
+}
+
+impl WeaponConfig {
+    pub fn is_explosive(&self) -> bool {
+        self.explosion_radius > 0.0
+    }
+
+    /// How much of the explosion reaches a character, 1.0 at the center down to 0.0 outside the radius.
+    pub fn explosion_scale(
+        &self,
+        explosion_position: Vec3,
+        character_position: Vec3,
+        character_radius: f32,
+    ) -> f32 {
+        if !self.is_explosive() {
+            return 0.0;
+        }
+        // measure to the edge of the character rather than its center
+        let distance =
+            (explosion_position.distance(character_position) - character_radius).max(0.0);
+        if distance > self.explosion_radius {
+            return 0.0;
+        }
+        self.explosion_falloff
+            .scale(distance / self.explosion_radius)
+    }
+
+    pub fn explosion_knockback(
+        &self,
+        explosion_position: Vec3,
+        character_position: Vec3,
+        character_radius: f32,
+    ) -> Vec3 {
+        self.scaled_knockback(
+            explosion_position,
+            character_position,
+            self.explosion_scale(explosion_position, character_position, character_radius),
+        )
+    }
+
+    /// Knockback away from the explosion at `scale` of its full strength, none when the
+    /// explosion doesn't reach.
+    pub fn scaled_knockback(
+        &self,
+        explosion_position: Vec3,
+        character_position: Vec3,
+        scale: f32,
+    ) -> Vec3 {
+        if scale <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let direction = (character_position - explosion_position).normalize_or(Vec3::Y);
+        direction * self.knockback_impulse * scale
+    }
 }
 
 pub fn get_weapon_config<'a>(
-    data_asset_handles: &Res<DataAssetHandles>,
-    weapon_configs: &'a Res<Assets<WeaponConfig>>,
+    data_asset_handles: &DataAssetHandles,
+    weapon_configs: &'a Assets<WeaponConfig>,
     tag: &str,
 ) -> Option<&'a WeaponConfig> {
     let handle = data_asset_handles.weapon_configs.get(tag)?;
@@ -44,14 +377,204 @@ pub fn get_weapon_config<'a>(
 pub struct WeaponFiredProjectileEvent {
     pub owner_client_id: u64,
     pub weapon_config_tag: String,
+    pub fired_input_id: u32,
     pub origin: Vec3,
     pub direction: Vec3,
 }
 
+#[derive(Event, Serialize, Deserialize, Clone, Debug)]
+pub struct WeaponDryFiredEvent {
+    pub owner_client_id: u64,
+    pub weapon_config_tag: String,
+}
+
 #[derive(Event, Serialize, Deserialize, Clone, Debug)]
 pub struct WeaponFiredHitscanEvent {
     pub owner_client_id: u64,
     pub weapon_config_tag: String,
+    pub fired_input_id: u32,
     pub origin: Vec3,
     pub endpoint: Vec3,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    /// The input the next run of `weapons_system` simulates.
+    #[derive(Resource, Default)]
+    struct CurrentInput(PlayerInput);
+
+    /// The input ticks the weapons fired on.
+    #[derive(Resource, Default)]
+    struct FireTicks(Vec<u32>);
+
+    fn weapons_system(
+        input: Res<CurrentInput>,
+        data_asset_handles: Res<DataAssetHandles>,
+        weapon_configs: Res<Assets<WeaponConfig>>,
+        mut fire_ticks: ResMut<FireTicks>,
+        mut inventories: Query<&mut WeaponInventory>,
+    ) {
+        for mut inventory in inventories.iter_mut() {
+            if let Some((WeaponAction::Fired, _)) =
+                inventory.update(&input.0, &data_asset_handles, &weapon_configs)
+            {
+                fire_ticks.0.push(input.0.id);
+            }
+        }
+    }
+
+    fn weapon_config(
+        tag: &str,
+        fire_rate_ms: u32,
+        switch_time_ms: u32,
+        magazine_size: u32,
+        reload_time_ms: u32,
+    ) -> WeaponConfig {
+        WeaponConfig {
+            tag: tag.into(),
+            name: tag.into(),
+            fire_mode: FireMode::Hitscan,
+            fire_rate_ms,
+            switch_time_ms,
+            damage: 10,
+            magazine_size,
+            reload_time_ms,
+            max_ammo: 100,
+            projectile_speed: 0.0,
+            projectile_gravity: 0.0,
+            explosion_radius: 0.0,
+            explosion_falloff: ExplosionFalloff::default(),
+            self_damage_multiplier: 0.0,
+            knockback_impulse: 0.0,
+        }
+    }
+
+    fn setup_world() -> World {
+        let mut world = World::new();
+        let mut weapon_configs = Assets::<WeaponConfig>::default();
+        let mut data_asset_handles = DataAssetHandles::default();
+        let mut inventory = WeaponInventory {
+            weapons: Vec::new(),
+            active_slot: 0,
+        };
+
+        for config in [
+            weapon_config("rifle", 100, 250, 7, 900),
+            weapon_config("launcher", 750, 400, 2, 1500),
+        ] {
+            inventory.give(&config, 20);
+            data_asset_handles
+                .weapon_configs
+                .insert(config.tag.clone(), weapon_configs.add(config));
+        }
+
+        world.insert_resource(weapon_configs);
+        world.insert_resource(data_asset_handles);
+        world.init_resource::<CurrentInput>();
+        world.init_resource::<FireTicks>();
+        world.spawn(inventory);
+        world
+    }
+
+    fn simulate(world: &mut World, input: &PlayerInput) {
+        world.resource_mut::<CurrentInput>().0 = input.clone();
+        world.run_system_once(weapons_system);
+    }
+
+    fn inventory_snapshot(world: &mut World) -> CharacterSnapshot {
+        let inventory = world.query::<&WeaponInventory>().single(world);
+        CharacterSnapshot {
+            owner_client_id: 0,
+            position: None,
+            yaw: None,
+            velocity: None,
+            is_crouching: None,
+            health: None,
+            armor: None,
+            active_weapon: Some(inventory.active_slot as u8),
+            weapons: Some(inventory.to_snapshot()),
+        }
+    }
+
+    /// Holds the trigger with a reload, a couple of switches and some gaps in between.
+    fn input_stream() -> Vec<PlayerInput> {
+        (1..=400)
+            .map(|id| PlayerInput {
+                id,
+                fire: !(90..110).contains(&id) && id % 50 != 0,
+                reload: id == 60,
+                switch_weapon: match id {
+                    150 => Some(1),
+                    300 => Some(0),
+                    _ => None,
+                },
+                ..default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replayed_inputs_fire_on_the_same_ticks_as_the_server() {
+        let inputs = input_stream();
+
+        // the server sometimes simulates the same input twice when the next one is late
+        let mut server_world = setup_world();
+        let mut server_states = Vec::new();
+        for input in &inputs {
+            simulate(&mut server_world, input);
+            if input.id % 7 == 0 {
+                simulate(&mut server_world, input);
+            }
+            server_states.push((input.id, inventory_snapshot(&mut server_world)));
+        }
+
+        // the client predicts every input, and every few ticks reconciles against a server
+        // state that is a few inputs behind, replaying everything after it
+        let mut client_world = setup_world();
+        for input in &inputs {
+            simulate(&mut client_world, input);
+
+            if input.id % 10 == 0 {
+                let acked_input_id = input.id - 4;
+                let (_, acked_state) = server_states
+                    .iter()
+                    .find(|(input_id, _)| *input_id == acked_input_id)
+                    .unwrap();
+                client_world
+                    .query::<&mut WeaponInventory>()
+                    .single_mut(&mut client_world)
+                    .apply_snapshot(acked_state);
+                client_world
+                    .resource_mut::<FireTicks>()
+                    .0
+                    .retain(|tick| *tick <= acked_input_id);
+                for replayed_input in inputs
+                    .iter()
+                    .filter(|replayed| replayed.id > acked_input_id && replayed.id <= input.id)
+                {
+                    simulate(&mut client_world, replayed_input);
+                }
+            }
+        }
+
+        let server_fire_ticks = &server_world.resource::<FireTicks>().0;
+        let client_fire_ticks = &client_world.resource::<FireTicks>().0;
+        assert!(!server_fire_ticks.is_empty());
+        assert_eq!(server_fire_ticks, client_fire_ticks);
+        assert_eq!(
+            inventory_snapshot(&mut server_world),
+            inventory_snapshot(&mut client_world)
+        );
+    }
+
+    #[test]
+    fn cooldowns_are_whole_ticks() {
+        assert_eq!(ms_to_ticks(0), 0);
+        assert_eq!(ms_to_ticks(1000), TICKRATE);
+        // anything less than a tick still has to wait for the next one
+        assert_eq!(ms_to_ticks(1), 1);
+    }
+}